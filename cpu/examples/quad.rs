@@ -0,0 +1,97 @@
+//! The colored quad gaem used to draw on its own, as a game: a sprite over the middle half of the
+//! window with a different color in each corner. F11 cycles through windowed, borderless and
+//! exclusive fullscreen, F9 through the present modes the window supports and F8 through its
+//! output modes.
+use gaem::{
+    glam::{vec4, Vec4},
+    AppConfig, Context, DisplayConfig, Frame, Game, KeyCode, Sprite, TextureId, WindowMode,
+    WrappedApp, ARGS_USAGE,
+};
+use std::{env, process};
+use tracing_subscriber::EnvFilter;
+use winit::event_loop::{ControlFlow, EventLoop};
+
+/// Loads the settings from the command line, printing the usage on `--help` or an error.
+fn config_from_args() -> AppConfig {
+    let usage = format!(
+        "Usage: quad [OPTIONS]\n\nOptions:\n{ARGS_USAGE}\n  \
+         -h, --help                 Print this help"
+    );
+    let args: Vec<_> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{usage}");
+        process::exit(0);
+    }
+    let mut config = AppConfig::default();
+    if let Err(err) = config.apply_args(args) {
+        eprintln!("error: {err}\n\n{usage}");
+        process::exit(2);
+    }
+    config
+}
+
+struct Quad {
+    display: DisplayConfig,
+}
+
+impl Game for Quad {
+    fn update(&mut self, ctx: &mut Context, _dt: f32) {
+        if ctx.input.key_pressed(KeyCode::F11) {
+            self.display.mode = match self.display.mode {
+                WindowMode::Windowed => WindowMode::Borderless,
+                WindowMode::Borderless => WindowMode::Exclusive,
+                WindowMode::Exclusive => WindowMode::Windowed,
+            };
+            ctx.set_display(ctx.main_window(), self.display.clone());
+        }
+        if ctx.input.key_pressed(KeyCode::F9) {
+            if let Some(support) = ctx.surface_support(ctx.main_window()) {
+                let mut config = ctx.swapchain_config();
+                let modes = &support.present_modes;
+                let next = modes
+                    .iter()
+                    .position(|m| *m == config.present_mode)
+                    .map_or(0, |i| (i + 1) % modes.len());
+                config.present_mode = modes[next];
+                ctx.set_swapchain_config(config);
+            }
+        }
+        if ctx.input.key_pressed(KeyCode::F8) {
+            let main = ctx.main_window();
+            let modes = ctx.output_modes(main);
+            let next = ctx
+                .output_mode(main)
+                .and_then(|mode| modes.iter().position(|m| *m == mode))
+                .map_or(0, |i| (i + 1) % modes.len());
+            ctx.set_output_mode(modes[next]);
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let sprite = Sprite::new(TextureId::WHITE, frame.size * 0.25, frame.size * 0.5)
+            .with_corner_colors([
+                vec4(1.0, 0.0, 0.0, 1.0),
+                vec4(0.0, 1.0, 0.0, 1.0),
+                vec4(0.0, 0.0, 1.0, 1.0),
+                Vec4::ONE,
+            ]);
+        frame.gpu_scope("quad", |frame| frame.sprites.draw(sprite));
+    }
+}
+
+fn main() {
+    // RUST_LOG overrides the default, e.g. RUST_LOG=gaem=trace for a span around every frame
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("gaem=info")),
+        )
+        .init();
+    let config = config_from_args();
+    let event_loop = EventLoop::new().unwrap();
+    event_loop.set_control_flow(ControlFlow::Poll);
+    let quad = Quad {
+        display: config.window.display.clone(),
+    };
+    let mut app = WrappedApp::with_config(quad, config);
+    event_loop.run_app(&mut app).expect("Failed to run app.");
+}
@@ -1,497 +1,726 @@
+use crate::{
+    capture::{Capture, CaptureConfig},
+    config::{AppConfig, MAX_FRAMES_IN_FLIGHT},
+    debug::DebugRenderer,
+    device::{select_device, GpuSelector, Queues},
+    display::DisplayConfig,
+    features::{create_device, DeviceCapabilities},
+    game::{Context, Frame, Game},
+    input::{must_track, Input},
+    label::{self, begin_label, end_label, name_object},
+    pipeline::{PassTarget, Rendering},
+    profiler::{FrameStats, GpuProfiler},
+    sprite::SpriteRenderer,
+    swapchain::SwapchainConfig,
+    sync::FrameSync,
+    text::{Fonts, TextRenderer},
+    texture::Textures,
+    time::{Clock, RedrawMode},
+    tonemap::{OutputMode, HDR_FORMAT},
+    util::{load_shader_module, Gpu},
+    validation::{DebugMessenger, InstanceValidation, ValidationConfig},
+    window::AppWindow,
+};
 use ash::{
-    ext::debug_utils,
-    khr::{surface, swapchain},
-    util::read_spv,
+    ext::swapchain_colorspace,
+    khr::{dynamic_rendering, surface, swapchain},
     vk, Device, Entry, Instance,
 };
-use glam::{vec2, vec3, Vec2, Vec3};
-use std::io::Cursor;
-use std::{ffi::CStr, os::raw::c_char};
+use glam::vec2;
+use std::{collections::HashMap, ffi::CString, time::Instant};
+use tracing::{debug, error, info_span, trace, trace_span};
 use winit::{
     application::ApplicationHandler,
-    dpi::PhysicalSize,
     event::WindowEvent,
-    event_loop::ActiveEventLoop,
+    event_loop::{ActiveEventLoop, ControlFlow},
     raw_window_handle::{HasDisplayHandle, HasWindowHandle},
-    window::{Window, WindowAttributes},
+    window::{Window, WindowAttributes, WindowId},
 };
 
-const MAX_IN_FLIGHT: usize = 2;
-
-#[derive(Clone, Copy)]
-#[repr(C, align(16))]
-struct Vertex {
-    pos: Vec2,
-    col: Vec3,
+/// The instance, device and everything else every window renders with.
+pub(crate) struct Shared {
+    pub entry: Entry,
+    pub instance: Instance,
+    pub debug_messenger: DebugMessenger,
+    pub pdevice: vk::PhysicalDevice,
+    pub caps: DeviceCapabilities,
+    pub mem_props: vk::PhysicalDeviceMemoryProperties,
+    pub device: Device,
+    pub queues: Queues,
+    pub surface_loader: surface::Instance,
+    pub swap_device: swapchain::Device,
+    pub swap_config: SwapchainConfig,
+    pub depth_format: vk::Format,
+    /// The scene pass, which every window's scene framebuffer is created against. Null with
+    /// dynamic rendering.
+    pub render_pass: vk::RenderPass,
+    /// Draws debug lines over the scene's color target. Null with dynamic rendering.
+    pub debug_pass: vk::RenderPass,
+    pub rendering: Rendering,
+    pub command_pool: vk::CommandPool,
+    pub transfer_pool: vk::CommandPool,
+    pub sync: FrameSync,
+    pub frames_in_flight: usize,
 }
 
-impl Vertex {
-    pub fn binding_descr() -> Vec<vk::VertexInputBindingDescription> {
-        vec![vk::VertexInputBindingDescription::default()
-            .stride(size_of::<Vertex>() as u32)
-            .binding(0)
-            .input_rate(vk::VertexInputRate::VERTEX)]
+impl Shared {
+    pub fn gpu(&self) -> Gpu<'_> {
+        Gpu {
+            device: &self.device,
+            mem_props: &self.mem_props,
+            command_pool: self.command_pool,
+            transfer_pool: self.transfer_pool,
+            queues: self.queues,
+        }
     }
 
-    pub fn attr_descr() -> Vec<vk::VertexInputAttributeDescription> {
-        vec![
-            vk::VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(0)
-                .format(vk::Format::R32G32_SFLOAT)
-                .offset(core::mem::offset_of!(Vertex, pos) as u32),
-            vk::VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(1)
-                .format(vk::Format::R32G32B32_SFLOAT)
-                .offset(core::mem::offset_of!(Vertex, col) as u32),
-        ]
+    /// What the scene pipelines draw into.
+    pub fn scene_target(&self) -> PassTarget {
+        if self.rendering.is_dynamic() {
+            PassTarget::Dynamic {
+                color: HDR_FORMAT,
+                depth: self.depth_format,
+            }
+        } else {
+            PassTarget::RenderPass(self.render_pass)
+        }
     }
-}
 
-const VERTICES: [Vertex; 4] = [
-    Vertex {
-        pos: vec2(-0.5, -0.5),
-        col: vec3(1.0, 0.0, 0.0),
-    },
-    Vertex {
-        pos: vec2(0.5, -0.5),
-        col: vec3(0.0, 1.0, 0.0),
-    },
-    Vertex {
-        pos: vec2(0.5, 0.5),
-        col: vec3(0.0, 0.0, 1.0),
-    },
-    Vertex {
-        pos: vec2(-0.5, 0.5),
-        col: vec3(1.0, 1.0, 1.0),
-    },
-];
-const INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
-
-struct App {
-    entry: Entry,
-    instance: Instance,
-    window: Window,
-    pdevice: vk::PhysicalDevice,
-    device: Device,
-    queue: vk::Queue,
-    surface: vk::SurfaceKHR,
-    surface_loader: surface::Instance,
-    swapchain: vk::SwapchainKHR,
-    swap_device: swapchain::Device,
-    swap_imgs: Vec<vk::Image>,
-    swap_img_views: Vec<vk::ImageView>,
-    swap_framebuffers: Vec<vk::Framebuffer>,
-    format: vk::SurfaceFormatKHR,
-    extent: vk::Extent2D,
-    pipeline_layout: vk::PipelineLayout,
-    render_pass: vk::RenderPass,
-    pipeline: vk::Pipeline,
-    command_pool: vk::CommandPool,
-    command_buffers: [vk::CommandBuffer; MAX_IN_FLIGHT],
-    image_available: [vk::Semaphore; MAX_IN_FLIGHT],
-    render_done: [vk::Semaphore; MAX_IN_FLIGHT],
-    in_flight: [vk::Fence; MAX_IN_FLIGHT],
-    cur_frame: usize,
-    resized: Option<PhysicalSize<u32>>,
-    vert_buff: (vk::Buffer, vk::DeviceMemory),
-    ind_buff: (vk::Buffer, vk::DeviceMemory),
-}
-pub struct WrappedApp(Option<App>);
-impl WrappedApp {
-    pub fn new() -> Self {
-        WrappedApp(None)
+    pub unsafe fn swap_support(
+        &self,
+        surface: vk::SurfaceKHR,
+    ) -> (
+        vk::SurfaceCapabilitiesKHR,
+        Vec<vk::SurfaceFormatKHR>,
+        Vec<vk::PresentModeKHR>,
+    ) {
+        (
+            self.surface_loader
+                .get_physical_device_surface_capabilities(self.pdevice, surface)
+                .expect("Failed to get device surface capabilities."),
+            self.surface_loader
+                .get_physical_device_surface_formats(self.pdevice, surface)
+                .expect("Failed to get device surface formats."),
+            self.surface_loader
+                .get_physical_device_surface_present_modes(self.pdevice, surface)
+                .expect("Failed to get device surface modes."),
+        )
     }
-}
 
-impl App {
-    unsafe fn render(&mut self) {
-        let img_idx;
-        self.device
-            .wait_for_fences(&[self.in_flight[self.cur_frame]], true, u64::MAX)
-            .expect("Failed to wait for fences.");
-        match self.swap_device.acquire_next_image(
-            self.swapchain,
-            u64::MAX,
-            self.image_available[self.cur_frame],
-            vk::Fence::null(),
-        ) {
-            Ok((idx, _)) => img_idx = idx,
-            Err(e) => {
-                if e == vk::Result::ERROR_OUT_OF_DATE_KHR {
-                    self.device.device_wait_idle().unwrap();
-                    self.clean_swapchain();
-                    self.create_swapchain();
-                    return;
-                } else {
-                    panic!("Failed to acquire next image.");
-                }
-            }
-        }
-        self.device
-            .reset_fences(&[self.in_flight[self.cur_frame]])
-            .expect("Failed to reset fences.");
-        self.device
-            .begin_command_buffer(
-                self.command_buffers[self.cur_frame],
-                &vk::CommandBufferBeginInfo::default(),
-            )
-            .expect("Failed to begin command buffer.");
-
-        let mut clear_color = [vk::ClearValue::default()];
-        clear_color[0].color.float32 = [0.0, 0.0, 0.0, 1.0];
-        let pass_info = vk::RenderPassBeginInfo::default()
-            .render_pass(self.render_pass)
-            .framebuffer(self.swap_framebuffers[img_idx as usize])
-            .render_area(vk::Rect2D::default().extent(self.extent))
-            .clear_values(&clear_color);
-        self.device
-            .reset_command_buffer(
-                self.command_buffers[self.cur_frame],
-                vk::CommandBufferResetFlags::empty(),
-            )
-            .expect("Failed to reset command buffer.");
-        self.device
-            .begin_command_buffer(
-                self.command_buffers[self.cur_frame],
-                &vk::CommandBufferBeginInfo::default(),
+    /// Creates a surface for `window`, or returns `None` if the device can't present to it.
+    pub unsafe fn create_surface(&self, window: &Window) -> Option<vk::SurfaceKHR> {
+        let surface = ash_window::create_surface(
+            &self.entry,
+            &self.instance,
+            window.display_handle().unwrap().as_raw(),
+            window.window_handle().unwrap().as_raw(),
+            None,
+        )
+        .expect("Failed to create surface.");
+        let supported = self
+            .surface_loader
+            .get_physical_device_surface_support(
+                self.pdevice,
+                self.queues.families.present,
+                surface,
             )
-            .expect("Failed to begin command buffer.");
-        self.device.cmd_begin_render_pass(
-            self.command_buffers[self.cur_frame],
-            &pass_info,
-            vk::SubpassContents::INLINE,
-        );
-        self.device.cmd_bind_pipeline(
-            self.command_buffers[self.cur_frame],
-            vk::PipelineBindPoint::GRAPHICS,
-            self.pipeline,
-        );
-        let viewport = [vk::Viewport::default()
-            .width(self.extent.width as f32)
-            .height(self.extent.height as f32)
-            .max_depth(1.0)];
-        let scissor = [vk::Rect2D::default().extent(self.extent)];
-        self.device
-            .cmd_set_viewport(self.command_buffers[self.cur_frame], 0, &viewport);
-        self.device
-            .cmd_set_scissor(self.command_buffers[self.cur_frame], 0, &scissor);
-        let vert_buffs = [self.vert_buff.0];
-        let offsets = [0];
-        self.device.cmd_bind_vertex_buffers(
-            self.command_buffers[self.cur_frame],
-            0,
-            &vert_buffs,
-            &offsets,
-        );
-        self.device.cmd_bind_index_buffer(
-            self.command_buffers[self.cur_frame],
-            self.ind_buff.0,
-            0,
-            vk::IndexType::UINT16,
-        );
-        self.device.cmd_draw_indexed(
-            self.command_buffers[self.cur_frame],
-            INDICES.len() as u32,
-            1,
-            0,
-            0,
-            0,
-        );
-        self.device
-            .cmd_end_render_pass(self.command_buffers[self.cur_frame]);
-        self.device
-            .end_command_buffer(self.command_buffers[self.cur_frame])
-            .expect("Failed to end command buffer.");
+            .unwrap_or(false);
+        if !supported {
+            self.surface_loader.destroy_surface(surface, None);
+            return None;
+        }
+        Some(surface)
+    }
 
-        let image_available = [self.image_available[self.cur_frame]];
-        let render_done = [self.render_done[self.cur_frame]];
-        let submit_info = vk::SubmitInfo::default()
-            .wait_semaphores(&image_available)
-            .signal_semaphores(&render_done)
-            .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-            .command_buffers(&self.command_buffers[self.cur_frame..=self.cur_frame]);
-        self.device
-            .queue_submit(self.queue, &[submit_info], self.in_flight[self.cur_frame])
-            .expect("Faild to submit frame.");
-        let swapchains = [self.swapchain];
-        let img_idxs = [img_idx];
-        let present_info = vk::PresentInfoKHR::default()
-            .wait_semaphores(&render_done)
-            .swapchains(&swapchains)
-            .image_indices(&img_idxs);
-        let res = self.swap_device.queue_present(self.queue, &present_info);
-        if res.is_err() || self.resized.is_some() {
-            if self.resized.is_some() || res.unwrap_err() == vk::Result::ERROR_OUT_OF_DATE_KHR {
-                if let Some(PhysicalSize { width, height }) = self.resized {
-                    if width == 0 || height == 0 {
-                        return;
-                    }
-                    self.resized = None;
-                }
-                self.device.device_wait_idle().unwrap();
-                self.clean_swapchain();
-                self.create_swapchain();
-                return;
-            } else {
-                panic!("Failed to acquire next image.");
+    /// What the debug line pipeline draws into.
+    pub fn debug_target(&self) -> PassTarget {
+        if self.rendering.is_dynamic() {
+            PassTarget::Dynamic {
+                color: HDR_FORMAT,
+                depth: vk::Format::UNDEFINED,
             }
+        } else {
+            PassTarget::RenderPass(self.debug_pass)
         }
-        self.cur_frame = (self.cur_frame + 1) % MAX_IN_FLIGHT;
     }
 
-    unsafe fn create_swapchain(&mut self) {
-        let (capabilities, formats, modes) =
-            App::get_swap_support(self.pdevice, &self.surface_loader, self.surface);
-        self.format = *formats
-            .iter()
-            .find(|f| {
-                f.format == vk::Format::B8G8R8A8_SRGB
-                    && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            })
-            .unwrap_or(&formats[0]);
-        self.extent = if capabilities.current_extent.width == u32::MAX {
-            capabilities.max_image_extent
-        } else {
-            capabilities.current_extent
-        };
-        self.swapchain = {
-            let mode = *modes
-                .iter()
-                .find(|m| **m == vk::PresentModeKHR::MAILBOX)
-                .unwrap_or(&vk::PresentModeKHR::FIFO);
-            let img_count = if capabilities.max_image_count == 0
-                || capabilities.max_image_count > capabilities.min_image_count
-            {
-                capabilities.min_image_count + 1
-            } else {
-                capabilities.max_image_count
-            };
-            let info = vk::SwapchainCreateInfoKHR::default()
-                .image_format(self.format.format)
-                .present_mode(mode)
-                .min_image_count(img_count)
-                .image_extent(self.extent)
-                .image_color_space(self.format.color_space)
-                .image_array_layers(1)
-                .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-                .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-                .queue_family_indices(&[])
-                .pre_transform(capabilities.current_transform)
-                .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-                .clipped(true)
-                .surface(self.surface)
-                .old_swapchain(vk::SwapchainKHR::null());
-            self.swap_device
-                .create_swapchain(&info, None)
-                .expect("Failed to create swapchain.")
-        };
-        self.swap_imgs = {
-            self.swap_device
-                .get_swapchain_images(self.swapchain)
-                .expect("Failed to retrieve swapchain image handles.")
-        };
-        self.swap_img_views = self
-            .swap_imgs
-            .iter()
-            .map(|i| {
-                let info = vk::ImageViewCreateInfo::default()
-                    .image(*i)
-                    .format(self.format.format)
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .components(
-                        vk::ComponentMapping::default()
-                            .r(vk::ComponentSwizzle::IDENTITY)
-                            .g(vk::ComponentSwizzle::IDENTITY)
-                            .b(vk::ComponentSwizzle::IDENTITY)
-                            .a(vk::ComponentSwizzle::IDENTITY),
-                    )
-                    .subresource_range(
-                        vk::ImageSubresourceRange::default()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .base_mip_level(0)
-                            .level_count(1)
-                            .base_array_layer(0)
-                            .layer_count(1),
-                    );
-                unsafe {
-                    self.device
-                        .create_image_view(&info, None)
-                        .expect("Failed to create image view.")
-                }
-            })
-            .collect();
+    /// Creates the scene render pass, which only depends on the depth format. Its attachments are
+    /// transitioned by the render graph, so they start and end in their attachment layouts.
+    pub unsafe fn create_scene_pass(&mut self) {
         self.render_pass = {
+            let attachment_desc = [
+                vk::AttachmentDescription::default()
+                    .format(HDR_FORMAT)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+                vk::AttachmentDescription::default()
+                    .format(self.depth_format)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .initial_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+            ];
+            let attachment_ref = [vk::AttachmentReference::default()
+                .attachment(0)
+                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+            let depth_ref = vk::AttachmentReference::default()
+                .attachment(1)
+                .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+            let subpass = [vk::SubpassDescription::default()
+                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                .color_attachments(&attachment_ref)
+                .depth_stencil_attachment(&depth_ref)];
+            let info = vk::RenderPassCreateInfo::default()
+                .attachments(&attachment_desc)
+                .subpasses(&subpass);
+            let render_pass = self
+                .device
+                .create_render_pass(&info, None)
+                .expect("Failed to create render pass.");
+            name_object(render_pass, "scene");
+            render_pass
+        };
+    }
+
+    /// Creates the debug render pass, which keeps what the scene drew to its color target.
+    pub unsafe fn create_debug_pass(&mut self) {
+        self.debug_pass = {
             let attachment_desc = [vk::AttachmentDescription::default()
-                .format(self.format.format)
+                .format(HDR_FORMAT)
                 .samples(vk::SampleCountFlags::TYPE_1)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .load_op(vk::AttachmentLoadOp::LOAD)
                 .store_op(vk::AttachmentStoreOp::STORE)
                 .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
                 .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-                .initial_layout(vk::ImageLayout::UNDEFINED)
-                .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)];
+                .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
             let attachment_ref = [vk::AttachmentReference::default()
                 .attachment(0)
-                // TODO: use better image layout for attachment ref
-                .layout(vk::ImageLayout::GENERAL)];
+                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
             let subpass = [vk::SubpassDescription::default()
                 .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
                 .color_attachments(&attachment_ref)];
-            let dependancies = [vk::SubpassDependency::default()
-                .src_subpass(vk::SUBPASS_EXTERNAL)
-                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-                .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)];
             let info = vk::RenderPassCreateInfo::default()
                 .attachments(&attachment_desc)
-                .subpasses(&subpass)
-                .dependencies(&dependancies);
-            self.device
+                .subpasses(&subpass);
+            let render_pass = self
+                .device
                 .create_render_pass(&info, None)
-                .expect("Failed to create render pass.")
+                .expect("Failed to create debug render pass.");
+            name_object(render_pass, "debug");
+            render_pass
         };
-        self.swap_framebuffers = (0..self.swap_img_views.len())
-            .map(|i| unsafe {
-                let attachments = [self.swap_img_views[i]];
-                let framebuffer_info = vk::FramebufferCreateInfo::default()
-                    .render_pass(self.render_pass)
-                    .attachments(&attachments)
-                    .width(self.extent.width)
-                    .height(self.extent.height)
-                    .layers(1);
-                self.device
-                    .create_framebuffer(&framebuffer_info, None)
-                    .expect("Failed to create swapchain framebuffer.")
-            })
-            .collect();
+    }
+}
+
+/// The shared renderer and the windows drawing with it.
+struct App {
+    shared: Shared,
+    windows: HashMap<WindowId, AppWindow>,
+    /// The window frames are paced by. Closing it quits.
+    main: WindowId,
+    output_mode: OutputMode,
+    textures: Textures,
+    sprite_renderer: SpriteRenderer,
+    fonts: Fonts,
+    text_renderer: TextRenderer,
+    debug_renderer: DebugRenderer,
+    input: Input,
+    clock: Clock,
+    gpu_profiler: GpuProfiler,
+    stats: FrameStats,
+    capture: Capture,
+    /// Windows the game opened, closed or switched to or from fullscreen, and the swapchain setup
+    /// and output mode it asked for, which is applied once the game returns.
+    opened: Vec<Window>,
+    closed: Vec<WindowId>,
+    displays: Vec<(WindowId, DisplayConfig)>,
+    swapchain: Option<SwapchainConfig>,
+    requested_output: Option<OutputMode>,
+}
+
+/// Runs `G` in a window once handed to the event loop.
+pub struct WrappedApp<G> {
+    app: Option<App>,
+    game: G,
+    config: AppConfig,
+    output_mode: OutputMode,
+    capture_config: CaptureConfig,
+}
+impl<G: Game> WrappedApp<G> {
+    pub fn new(game: G) -> Self {
+        WrappedApp::with_config(game, AppConfig::default())
     }
 
-    unsafe fn clean_swapchain(&mut self) {
-        for i in 0..self.swap_framebuffers.len() {
-            self.device
-                .destroy_framebuffer(self.swap_framebuffers[i], None);
+    /// `config` should have passed `AppConfig::validate`; the setters below override it.
+    pub fn with_config(game: G, config: AppConfig) -> Self {
+        WrappedApp {
+            app: None,
+            game,
+            config,
+            output_mode: OutputMode::Sdr,
+            capture_config: CaptureConfig::default(),
         }
-        for i in 0..self.swap_img_views.len() {
-            self.device.destroy_image_view(self.swap_img_views[i], None);
+    }
+
+    /// Enables validation, unless the `GAEM_VALIDATION` environment variable overrides it. Only
+    /// takes effect before the window is created.
+    pub fn set_validation(&mut self, config: ValidationConfig) {
+        self.config.validation = config;
+    }
+
+    /// Uses the GPU matching `selector` instead of the best scoring one, unless the `GAEM_GPU`
+    /// environment variable is set. Only takes effect before the window is created.
+    pub fn set_gpu(&mut self, selector: GpuSelector) {
+        self.config.gpu = Some(selector);
+    }
+
+    /// The optional features the device was created with, once the window exists.
+    pub fn capabilities(&self) -> Option<DeviceCapabilities> {
+        self.app.as_ref().map(|app| app.shared.caps)
+    }
+
+    /// How many frames the CPU may record ahead of the GPU. More hides stalls better at the cost of
+    /// latency and memory for per-frame buffers. Only takes effect before the window is created.
+    pub fn set_frames_in_flight(&mut self, count: usize) {
+        assert!(
+            (1..=MAX_FRAMES_IN_FLIGHT).contains(&count),
+            "From 1 to {MAX_FRAMES_IN_FLIGHT} frames can be in flight."
+        );
+        self.config.frames_in_flight = count;
+    }
+
+    /// The output mode windows start with. Windows whose surface lacks it fall back to SDR. Only
+    /// takes effect before the window is created; games switch it while running with
+    /// `Context::set_output_mode`.
+    pub fn set_output_mode(&mut self, mode: OutputMode) {
+        self.output_mode = mode;
+    }
+
+    pub fn set_capture_config(&mut self, config: CaptureConfig) {
+        self.capture_config = config;
+        if let Some(app) = &mut self.app {
+            app.capture.config = config;
+        }
+    }
+
+    /// Switches the main window between windowed and fullscreen, or sets how it starts if it
+    /// doesn't exist yet.
+    pub fn set_display(&mut self, config: DisplayConfig) {
+        if let Some(app) = &mut self.app {
+            app.set_display(app.main, &config);
         }
-        self.swap_device.destroy_swapchain(self.swapchain, None);
-        self.device.destroy_render_pass(self.render_pass, None);
+        self.config.window.display = config;
+    }
+
+    /// The present mode and image count windows start with. Only takes effect before the window
+    /// is created; games change it while running with `Context::set_swapchain_config`.
+    pub fn set_swapchain_config(&mut self, config: SwapchainConfig) {
+        self.config.swapchain = config;
     }
+}
 
-    unsafe fn copy_buff(&self, size: u64, src: vk::Buffer, dst: vk::Buffer) {
-        let create_info = vk::CommandBufferAllocateInfo::default()
-            .level(vk::CommandBufferLevel::PRIMARY)
-            .command_buffer_count(1)
-            .command_pool(self.command_pool);
-        let command_buff = self
+impl App {
+    /// Renders what `game` queues for window `id`. The main window's frames also run the fixed
+    /// simulation steps that are due, and are the ones captured and timed.
+    unsafe fn frame(&mut self, event_loop: &ActiveEventLoop, id: WindowId, game: &mut impl Game) {
+        if self.windows[&id].minimized {
+            return;
+        }
+        let main = id == self.main;
+        let start = Instant::now();
+        let _span = trace_span!("frame", number = self.clock.frame_count(), window = ?id).entered();
+        if main {
+            for _ in 0..self.clock.tick() {
+                let dt = self.clock.fixed_dt();
+                self.input.begin_step();
+                game.update(&mut self.context(event_loop, id), dt);
+                self.input.end_step();
+            }
+            if let Some(key) = self.capture.config.hotkey {
+                if self.input.key_pressed(key) {
+                    self.capture.request(self.capture.config.frames);
+                }
+            }
+        }
+        let win = self.windows.get_mut(&id).unwrap();
+        let mut frame = Frame {
+            window: id,
+            sprites: &mut win.sprites,
+            texts: &mut win.texts,
+            fonts: &self.fonts,
+            egui: win.egui_state.egui_ctx(),
+            input: &self.input,
+            clock: &self.clock,
+            stats: &self.stats,
+            size: vec2(win.extent.width as f32, win.extent.height as f32),
+            view_proj: win.view_proj,
+        };
+        game.render(&mut frame);
+        win.view_proj = frame.view_proj;
+        if !main {
+            self.render(id);
+            return;
+        }
+        self.capture.begin_frame(&self.windows[&id].window);
+        self.render(id);
+        self.capture.end_frame(&self.windows[&id].window);
+        self.input.end_frame();
+        self.stats
+            .record_cpu(start.elapsed().as_secs_f32() * 1000.0);
+    }
+
+    /// What the game gets outside of rendering, with `id` as the window at hand.
+    fn context<'a>(&'a mut self, event_loop: &'a ActiveEventLoop, id: WindowId) -> Context<'a> {
+        Context {
+            window: &self.windows[&id].window,
+            input: &mut self.input,
+            clock: &mut self.clock,
+            fonts: &mut self.fonts,
+            stats: &self.stats,
+            textures: &mut self.textures,
+            capture: &mut self.capture,
+            event_loop,
+            shared: &self.shared,
+            windows: &self.windows,
+            main: self.main,
+            opened: &mut self.opened,
+            closed: &mut self.closed,
+            displays: &mut self.displays,
+            swapchain: &mut self.swapchain,
+            requested_output: &mut self.requested_output,
+        }
+    }
+
+    unsafe fn render(&mut self, id: WindowId) {
+        let main = id == self.main;
+        // frames are numbered across windows, so all of them decide which have finished
+        let completed = self.shared.sync.completed(
+            &self.shared.device,
+            self.windows.values().flat_map(|w| &w.frames),
+        );
+        self.textures.free_retired(&self.shared.device, completed);
+        let win = self.windows.get_mut(&id).unwrap();
+        self.shared
+            .sync
+            .wait(&self.shared.device, &win.frames[win.cur_frame]);
+        // only the main window's timings are kept, the others are read to reuse their queries
+        let mut discarded = FrameStats::default();
+        self.gpu_profiler.collect(
+            &self.shared.device,
+            &mut win.frames[win.cur_frame],
+            if main {
+                &mut self.stats
+            } else {
+                &mut discarded
+            },
+        );
+        // a suboptimal image can still be rendered to and presented, the swapchain is replaced
+        // right after
+        let (img_idx, suboptimal) = match self.shared.swap_device.acquire_next_image(
+            win.swapchain,
+            u64::MAX,
+            win.frames[win.cur_frame].image_available,
+            vk::Fence::null(),
+        ) {
+            Ok(acquired) => acquired,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                win.recreate_swapchain(&self.shared);
+                // nothing is drawn, so what was queued is dropped rather than drawn along with
+                // the next frame's, except egui's texture changes, which it won't send again
+                win.sprites.clear();
+                win.texts.clear();
+                if main {
+                    self.debug_renderer.skip_frame();
+                }
+                let egui_output = win.egui_state.egui_ctx().end_pass();
+                win.egui_state
+                    .handle_platform_output(&win.window, egui_output.platform_output);
+                win.egui_renderer.update_textures(
+                    self.shared.gpu(),
+                    &mut self.textures,
+                    egui_output.textures_delta,
+                    self.shared.sync.submitted() + 1,
+                    completed,
+                );
+                win.begin_ui_pass();
+                return;
+            }
+            Err(_) => panic!("Failed to acquire next image."),
+        };
+        let gpu = self.shared.gpu();
+        self.text_renderer.prepare(
+            gpu,
+            &mut win.frames[win.cur_frame],
+            &self.textures,
+            &self.fonts,
+            &mut win.texts,
+        );
+        let egui_ctx = win.egui_state.egui_ctx().clone();
+        let egui_output = egui_ctx.end_pass();
+        win.egui_state
+            .handle_platform_output(&win.window, egui_output.platform_output);
+        let primitives = egui_ctx.tessellate(egui_output.shapes, egui_output.pixels_per_point);
+        win.egui_renderer.update_textures(
+            gpu,
+            &mut self.textures,
+            egui_output.textures_delta,
+            self.shared.sync.submitted() + 1,
+            completed,
+        );
+        self.sprite_renderer.reserve(
+            gpu,
+            win.sprites.len(),
+            self.shared.sync.submitted(),
+            completed,
+        );
+        win.egui_renderer.prepare(
+            gpu,
+            &mut win.frames[win.cur_frame],
+            primitives,
+            egui_output.pixels_per_point,
+        );
+        let cmd = win.frames[win.cur_frame].cmd;
+        self.shared
+            .device
+            .reset_command_buffer(cmd, vk::CommandBufferResetFlags::empty())
+            .expect("Failed to reset command buffer.");
+        self.shared
             .device
-            .allocate_command_buffers(&create_info)
-            .expect("Faileed to allocate command buffers while copying staging -> vertex.");
-        let begin_info = vk::CommandBufferBeginInfo::default()
-            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-        self.device
-            .begin_command_buffer(command_buff[0], &begin_info)
+            .begin_command_buffer(cmd, &vk::CommandBufferBeginInfo::default())
             .expect("Failed to begin command buffer.");
-        let copy_reg = [vk::BufferCopy::default().size(size)];
-        self.device
-            .cmd_copy_buffer(command_buff[0], src, dst, &copy_reg);
-        self.device
-            .end_command_buffer(command_buff[0])
+        self.gpu_profiler
+            .reset(&self.shared.device, &win.frames[win.cur_frame]);
+        self.gpu_profiler
+            .begin(&self.shared.device, &mut win.frames[win.cur_frame], "frame");
+        let viewport = [vk::Viewport::default()
+            .width(win.extent.width as f32)
+            .height(win.extent.height as f32)
+            .max_depth(1.0)];
+        let scissor = [vk::Rect2D::default().extent(win.extent)];
+        let graph = &mut win.frame_graph;
+        graph.graph.set_image(
+            graph.swapchain,
+            win.swap_imgs[img_idx as usize],
+            win.swap_img_views[img_idx as usize],
+        );
+        for pass in win.frame_graph.graph.schedule() {
+            let graph = &win.frame_graph;
+            graph.graph.begin_pass(&self.shared.device, cmd, pass);
+            self.gpu_profiler.begin(
+                &self.shared.device,
+                &mut win.frames[win.cur_frame],
+                graph.graph.pass_name(pass),
+            );
+            if pass == graph.scene {
+                win.begin_scene(&self.shared, cmd);
+                self.shared.device.cmd_set_viewport(cmd, 0, &viewport);
+                self.shared.device.cmd_set_scissor(cmd, 0, &scissor);
+                // the game's scopes are labeled inside this one
+                begin_label(cmd, "sprites");
+                self.sprite_renderer.flush(
+                    gpu,
+                    &mut win.frames[win.cur_frame],
+                    &mut win.sprites,
+                    &self.textures,
+                    win.extent,
+                    &mut self.gpu_profiler,
+                );
+                end_label(cmd);
+                begin_label(cmd, "text");
+                self.text_renderer.draw(
+                    &self.shared.device,
+                    &win.frames[win.cur_frame],
+                    &self.textures,
+                    win.extent,
+                );
+                end_label(cmd);
+                win.end_pass(&self.shared, cmd);
+            } else if pass == graph.debug {
+                win.begin_debug(&self.shared, cmd);
+                self.shared.device.cmd_set_viewport(cmd, 0, &viewport);
+                self.shared.device.cmd_set_scissor(cmd, 0, &scissor);
+                // debug shapes are drawn in the main window only
+                if main {
+                    begin_label(cmd, "debug shapes");
+                    self.debug_renderer
+                        .flush(gpu, &mut win.frames[win.cur_frame], win.view_proj);
+                    end_label(cmd);
+                }
+                win.end_pass(&self.shared, cmd);
+            } else if pass == graph.output {
+                win.begin_output(&self.shared, cmd, img_idx as usize);
+                self.shared.device.cmd_set_viewport(cmd, 0, &viewport);
+                self.shared.device.cmd_set_scissor(cmd, 0, &scissor);
+                begin_label(cmd, "tone map");
+                win.tonemapper.draw(&self.shared.device, cmd, win.format);
+                end_label(cmd);
+                begin_label(cmd, "ui");
+                win.egui_renderer.draw(
+                    &self.shared.device,
+                    &win.frames[win.cur_frame],
+                    &self.textures,
+                    win.extent,
+                    win.format,
+                );
+                end_label(cmd);
+                win.end_pass(&self.shared, cmd);
+            }
+            self.gpu_profiler
+                .end(&self.shared.device, &mut win.frames[win.cur_frame]);
+        }
+        win.frame_graph.graph.finish(&self.shared.device, cmd);
+        self.gpu_profiler
+            .end(&self.shared.device, &mut win.frames[win.cur_frame]);
+        self.shared
+            .device
+            .end_command_buffer(cmd)
             .expect("Failed to end command buffer.");
-        let submit_info = [vk::SubmitInfo::default().command_buffers(&command_buff)];
-        self.device
-            .queue_submit(self.queue, &submit_info, vk::Fence::null())
-            .expect("Failed to submit to queue.");
-        self.device
-            .queue_wait_idle(self.queue)
-            .expect("Failed to wait idle.");
-        self.device
-            .free_command_buffers(self.command_pool, &command_buff);
+
+        self.shared.sync.submit(
+            &self.shared.device,
+            self.shared.queues.graphics,
+            &mut win.frames[win.cur_frame],
+        );
+        let render_done = [win.frames[win.cur_frame].render_done];
+        win.begin_ui_pass();
+        let swapchains = [win.swapchain];
+        let img_idxs = [img_idx];
+        let present_info = vk::PresentInfoKHR::default()
+            .wait_semaphores(&render_done)
+            .swapchains(&swapchains)
+            .image_indices(&img_idxs);
+        let out_of_date = match self
+            .shared
+            .swap_device
+            .queue_present(self.shared.queues.present, &present_info)
+        {
+            Ok(present_suboptimal) => suboptimal || present_suboptimal,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => true,
+            Err(_) => panic!("Failed to present image."),
+        };
+        // the frame was submitted either way, so its slot is in use
+        win.cur_frame = (win.cur_frame + 1) % win.frames.len();
+        if out_of_date || win.resized.take().is_some() {
+            win.recreate_swapchain(&self.shared);
+        }
     }
 
-    unsafe fn get_swap_support(
-        pdevice: vk::PhysicalDevice,
-        surface_loader: &surface::Instance,
-        surface: vk::SurfaceKHR,
-    ) -> (
-        vk::SurfaceCapabilitiesKHR,
-        Vec<vk::SurfaceFormatKHR>,
-        Vec<vk::PresentModeKHR>,
-    ) {
-        (
-            surface_loader
-                .get_physical_device_surface_capabilities(pdevice, surface)
-                .expect("Failed to get device surface capabilities."),
-            surface_loader
-                .get_physical_device_surface_formats(pdevice, surface)
-                .expect("Failed to get device surface formats."),
-            surface_loader
-                .get_physical_device_surface_present_modes(pdevice, surface)
-                .expect("Failed to get device surface modes."),
-        )
+    /// Switches window `id` to `config`. Its swapchain is recreated on the next frame, since the
+    /// switch doesn't resize the window on every platform, for example between borderless and
+    /// exclusive fullscreen at the same resolution.
+    fn set_display(&mut self, id: WindowId, config: &DisplayConfig) {
+        let Some(win) = self.windows.get_mut(&id) else {
+            return;
+        };
+        let window = &win.window;
+        let current = window
+            .current_monitor()
+            .or_else(|| window.primary_monitor());
+        window.set_fullscreen(config.fullscreen(window.available_monitors(), current));
+        win.resized = Some(window.inner_size());
+        debug!(window = ?id, mode = ?config.mode, "Changed window mode");
     }
 
-    fn basic(
-        entry: Entry,
-        instance: Instance,
-        window: Window,
-        device: Device,
-        surface_loader: surface::Instance,
-        swap_device: swapchain::Device,
-    ) -> Self {
-        App {
-            entry,
-            instance,
-            window,
-            pdevice: vk::PhysicalDevice::default(),
-            device,
-            queue: vk::Queue::default(),
-            surface: vk::SurfaceKHR::default(),
-            surface_loader,
-            swapchain: vk::SwapchainKHR::default(),
-            swap_device,
-            swap_imgs: Vec::<vk::Image>::default(),
-            swap_img_views: Vec::<vk::ImageView>::default(),
-            swap_framebuffers: Vec::<vk::Framebuffer>::default(),
-            format: vk::SurfaceFormatKHR::default(),
-            extent: vk::Extent2D::default(),
-            pipeline_layout: vk::PipelineLayout::default(),
-            render_pass: vk::RenderPass::default(),
-            pipeline: vk::Pipeline::default(),
-            command_pool: vk::CommandPool::default(),
-            command_buffers: [vk::CommandBuffer::default(); MAX_IN_FLIGHT],
-            image_available: [vk::Semaphore::default(); MAX_IN_FLIGHT],
-            render_done: [vk::Semaphore::default(); MAX_IN_FLIGHT],
-            in_flight: [vk::Fence::default(); MAX_IN_FLIGHT],
-            cur_frame: 0,
-            resized: None,
-            vert_buff: (vk::Buffer::default(), vk::DeviceMemory::default()),
-            ind_buff: (vk::Buffer::default(), vk::DeviceMemory::default()),
+    /// Sets up the windows the game opened, switches the ones it asked to and tears down the ones
+    /// it closed. Closing the main window quits instead.
+    unsafe fn apply_window_requests(&mut self, event_loop: &ActiveEventLoop) {
+        for window in std::mem::take(&mut self.opened) {
+            let id = window.id();
+            let Some(surface) = self.shared.create_surface(&window) else {
+                error!(window = ?id, "The GPU can't present to the window, closing it.");
+                continue;
+            };
+            let win = AppWindow::new(
+                &self.shared,
+                window,
+                surface,
+                self.output_mode,
+                &self.textures,
+                &self.gpu_profiler,
+            );
+            debug!(window = ?id, "Opened window");
+            self.windows.insert(id, win);
+        }
+        for (id, config) in std::mem::take(&mut self.displays) {
+            self.set_display(id, &config);
+        }
+        if let Some(config) = self.swapchain.take() {
+            self.shared.swap_config = config;
+            for win in self.windows.values_mut() {
+                win.recreate_swapchain(&self.shared);
+            }
+        }
+        if let Some(mode) = self.requested_output.take() {
+            self.output_mode = mode;
+            for win in self.windows.values_mut() {
+                win.set_output_mode(&self.shared, mode);
+            }
+        }
+        for id in std::mem::take(&mut self.closed) {
+            if id == self.main {
+                self.shared.device.device_wait_idle().unwrap();
+                event_loop.exit();
+                continue;
+            }
+            if let Some(mut win) = self.windows.remove(&id) {
+                self.shared.device.device_wait_idle().unwrap();
+                win.destroy(&self.shared);
+                debug!(window = ?id, "Closed window");
+            }
         }
     }
 }
 
-impl ApplicationHandler for WrappedApp {
+impl<G: Game> ApplicationHandler for WrappedApp<G> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let window = event_loop
             .create_window(
                 WindowAttributes::default()
-                    .with_inner_size(winit::dpi::LogicalSize::new(800.0, 600.0))
-                    .with_title("gaem"),
+                    .with_inner_size(winit::dpi::LogicalSize::new(
+                        self.config.window.width,
+                        self.config.window.height,
+                    ))
+                    .with_title(&self.config.window.title)
+                    .with_fullscreen(self.config.window.display.fullscreen(
+                        event_loop.available_monitors(),
+                        event_loop.primary_monitor(),
+                    )),
             )
             .unwrap();
         let entry = ash::Entry::linked();
+        // dynamic rendering needs Vulkan 1.3, or 1.2 with the extension; 1.0 loaders can only
+        // create 1.0 instances
+        let api_version = unsafe { entry.try_enumerate_instance_version() }
+            .ok()
+            .flatten()
+            .unwrap_or(vk::API_VERSION_1_0)
+            .min(vk::API_VERSION_1_3);
+        let validation =
+            unsafe { InstanceValidation::new(&entry, self.config.validation.with_env()) };
         let instance = {
-            let layer_names = [c"VK_LAYER_KHRONOS_validation"];
-            let layer_names_raw: Vec<*const c_char> = layer_names
-                .iter()
-                .map(|raw_name| raw_name.as_ptr())
-                .collect();
+            let _span = info_span!(
+                "create_instance",
+                api_version = %format!(
+                    "{}.{}",
+                    vk::api_version_major(api_version),
+                    vk::api_version_minor(api_version)
+                )
+            )
+            .entered();
+            let app_name = CString::new(self.config.app_name.as_str())
+                .expect("App name contains a NUL character.");
             let app_info = vk::ApplicationInfo::default()
-                .api_version(vk::make_api_version(0, 1, 0, 0))
-                .application_name(c"gaem")
+                .api_version(api_version)
+                .application_name(&app_name)
                 .application_version(0)
                 .engine_name(c"gaem")
                 .engine_version(0);
@@ -500,26 +729,20 @@ impl ApplicationHandler for WrappedApp {
             )
             .expect("Failed to enumerate required extensions.")
             .to_vec();
-            extension_names.push(debug_utils::NAME.as_ptr());
-            dbg!(&extension_names);
+            // needed for the HDR color spaces, which surfaces only report when it is enabled
+            let has_colorspace_ext = unsafe { entry.enumerate_instance_extension_properties(None) }
+                .unwrap_or_default()
+                .iter()
+                .any(|p| p.extension_name_as_c_str() == Ok(swapchain_colorspace::NAME));
+            if has_colorspace_ext {
+                extension_names.push(swapchain_colorspace::NAME.as_ptr());
+            }
             let instance_info = vk::InstanceCreateInfo::default()
                 .application_info(&app_info)
-                .enabled_layer_names(&layer_names_raw)
-                .enabled_extension_names(&extension_names)
                 .flags(vk::InstanceCreateFlags::default());
-            unsafe { entry.create_instance(&instance_info, None).unwrap() }
-        };
-        let extension_names = [swapchain::NAME];
-        let extension_names_raw = [swapchain::NAME.as_ptr()];
-        let check_dev_props_valid = |props: &Vec<vk::ExtensionProperties>| {
-            extension_names.iter().all(|e| {
-                props
-                    .into_iter()
-                    .map(|p| p.extension_name_as_c_str().unwrap())
-                    .collect::<Vec<&CStr>>()
-                    .contains(e)
-            })
+            unsafe { validation.create_instance(&entry, instance_info, &extension_names) }
         };
+        let debug_messenger = unsafe { DebugMessenger::new(&entry, &instance, &validation) };
         let surface_loader = surface::Instance::new(&entry, &instance);
         let surface = unsafe {
             ash_window::create_surface(
@@ -531,276 +754,219 @@ impl ApplicationHandler for WrappedApp {
             )
             .unwrap()
         };
-        let (queue_ind, pdevice) = unsafe {
-            let check_device = |d: vk::PhysicalDevice, i: u32, info: &vk::QueueFamilyProperties| {
-                info.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-                        && surface_loader
-                            .get_physical_device_surface_support(d, i, surface)
-                            .unwrap_or(false)
-                        && instance
-                            .enumerate_device_extension_properties(d)
-                            .map(|props| check_dev_props_valid(&props))
-                            .unwrap_or(false)
-                        // only query support after verifying extensions
-                        && {
-                            let (_, formats, modes) = App::get_swap_support(d, &surface_loader, surface);
-                            !formats.is_empty() && !modes.is_empty()
-                        }
-            };
-            instance
-                .enumerate_physical_devices()
-                .expect("Failed to enumerate devices.")
-                .iter()
-                .find_map(|d| {
-                    instance
-                        .get_physical_device_queue_family_properties(*d)
-                        .iter()
-                        .enumerate()
-                        .find_map(|(i, info)| {
-                            check_device(*d, i as u32, info).then(|| (i as u32, *d))
-                        })
-                })
-                .expect("Failed to find suitable device")
-        };
-        let device = unsafe {
-            let q_infos = [vk::DeviceQueueCreateInfo::default()
-                .queue_priorities(&[1.0])
-                .queue_family_index(queue_ind)];
-            let features = instance.get_physical_device_features(pdevice);
-            let device_info = vk::DeviceCreateInfo::default()
-                .enabled_features(&features)
-                .enabled_extension_names(&extension_names_raw)
-                .queue_create_infos(&q_infos);
-            instance
-                .create_device(pdevice, &device_info, None)
-                .expect("Failed to create device.")
+        let device_span = info_span!("create_device").entered();
+        let (pdevice, caps, families) = unsafe {
+            select_device(
+                &instance,
+                api_version,
+                &surface_loader,
+                surface,
+                self.config.gpu.as_ref(),
+            )
         };
-        let swap_device = swapchain::Device::new(&instance, &device);
-        let mut app = App::basic(entry, instance, window, device, surface_loader, swap_device);
-        app.pdevice = pdevice;
-        app.surface = surface;
-        // queue / swapchain
-        unsafe {
-            app.create_swapchain();
-            app.queue = app.device.get_device_queue(queue_ind, 0);
-        }
-        // pipeline
-        unsafe {
-            let shader_code = read_spv(&mut Cursor::new(&include_bytes!("../../shaders/target/spirv-builder/spirv-unknown-spv1.0/release/deps/shader_crate.spv")[..])).expect("Failed to read shader spv.");
-            let info = vk::ShaderModuleCreateInfo::default().code(&shader_code);
-            let shader_module = app
-                .device
-                .create_shader_module(&info, None)
-                .expect("Failed to create shader module.");
-            let shader_stage = |name, flags| {
-                vk::PipelineShaderStageCreateInfo::default()
-                    .stage(flags)
-                    .module(shader_module)
-                    .name(name)
-            };
-            let shader_stage_info = [
-                shader_stage(c"vert_main", vk::ShaderStageFlags::VERTEX),
-                shader_stage(c"frag_main", vk::ShaderStageFlags::FRAGMENT),
-            ];
-            let binding_descrs = Vertex::binding_descr();
-            let attr_descrs = Vertex::attr_descr();
-            let vert_in_info = vk::PipelineVertexInputStateCreateInfo::default()
-                .vertex_binding_descriptions(&binding_descrs)
-                .vertex_attribute_descriptions(&attr_descrs);
-            let dyn_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-            let dyn_state_info =
-                vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dyn_states);
-            let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::default()
-                .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-                .primitive_restart_enable(false);
-            let viewport_info = vk::PipelineViewportStateCreateInfo::default()
-                .viewport_count(1)
-                .scissor_count(1);
-            let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::default()
-                .depth_clamp_enable(false)
-                .polygon_mode(vk::PolygonMode::FILL)
-                .line_width(1.0)
-                .cull_mode(vk::CullModeFlags::BACK)
-                .front_face(vk::FrontFace::CLOCKWISE)
-                .depth_bias_enable(false);
-            let multisampling_info = vk::PipelineMultisampleStateCreateInfo::default()
-                .sample_shading_enable(false)
-                .rasterization_samples(vk::SampleCountFlags::TYPE_1);
-            let blending_attachment = [vk::PipelineColorBlendAttachmentState::default()
-                .color_write_mask(vk::ColorComponentFlags::RGBA)
-                .blend_enable(true)
-                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                .color_blend_op(vk::BlendOp::ADD)
-                .src_alpha_blend_factor(vk::BlendFactor::ONE)
-                .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-                .alpha_blend_op(vk::BlendOp::ADD)];
-            let blending_info = vk::PipelineColorBlendStateCreateInfo::default()
-                .logic_op_enable(false)
-                .attachments(&blending_attachment);
-            let layout_info = vk::PipelineLayoutCreateInfo::default();
-            app.pipeline_layout = app
-                .device
-                .create_pipeline_layout(&layout_info, None)
-                .expect("Failed to create pipeline layout.");
-            let pipeline_info = [vk::GraphicsPipelineCreateInfo::default()
-                .stages(&shader_stage_info)
-                .vertex_input_state(&vert_in_info)
-                .input_assembly_state(&input_assembly_info)
-                .viewport_state(&viewport_info)
-                .rasterization_state(&rasterizer_info)
-                .multisample_state(&multisampling_info)
-                .color_blend_state(&blending_info)
-                .dynamic_state(&dyn_state_info)
-                .layout(app.pipeline_layout)
-                .render_pass(app.render_pass)
-                .subpass(0)];
-            app.pipeline = app
-                .device
-                .create_graphics_pipelines(vk::PipelineCache::null(), &pipeline_info, None)
-                .expect("Failed to create graphics pipeline.")[0];
-            app.device.destroy_shader_module(shader_module, None);
+        let device = unsafe { create_device(&instance, pdevice, &caps, &families) };
+        drop(device_span);
+        if validation.debug_utils() {
+            unsafe { label::init(&instance, &device) };
         }
-        // command pool
-        unsafe {
+        let swap_device = swapchain::Device::new(&instance, &device);
+        let queues = unsafe { Queues::new(&device, families) };
+        let rendering = if caps.dynamic_rendering_khr {
+            Rendering::Khr(dynamic_rendering::Device::new(&instance, &device))
+        } else if caps.dynamic_rendering {
+            Rendering::Core
+        } else {
+            Rendering::RenderPass
+        };
+        let sync =
+            unsafe { FrameSync::new(&device, caps.timeline_semaphore, caps.synchronization2) };
+        let mem_props = unsafe { instance.get_physical_device_memory_properties(pdevice) };
+        let depth_format = [
+            vk::Format::D32_SFLOAT,
+            vk::Format::D32_SFLOAT_S8_UINT,
+            vk::Format::D24_UNORM_S8_UINT,
+        ]
+        .into_iter()
+        .find(|f| unsafe {
+            instance
+                .get_physical_device_format_properties(pdevice, *f)
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .expect("Failed to find supported depth format.");
+        // command pools
+        let (command_pool, transfer_pool) = unsafe {
             let pool_info = vk::CommandPoolCreateInfo::default()
                 .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
-                .queue_family_index(queue_ind);
-            app.command_pool = app
-                .device
+                .queue_family_index(families.graphics);
+            let command_pool = device
                 .create_command_pool(&pool_info, None)
                 .expect("Failed to create command pool.");
-        }
-        // command buffers
-        unsafe {
-            let buff_info = vk::CommandBufferAllocateInfo::default()
-                .command_pool(app.command_pool)
-                .level(vk::CommandBufferLevel::PRIMARY)
-                .command_buffer_count(1);
-            for i in 0..MAX_IN_FLIGHT {
-                app.command_buffers[i] = app
-                    .device
-                    .allocate_command_buffers(&buff_info)
-                    .expect("Failed to allocate command buffers")[0]
-            }
-        }
-        // semaphores / fences
-        unsafe {
-            let fence_create_info =
-                vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
-            for i in 0..MAX_IN_FLIGHT {
-                app.image_available[i] = app
-                    .device
-                    .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
-                    .expect("Failed to create semaphore.");
-                app.render_done[i] = app
-                    .device
-                    .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
-                    .expect("Failed to create semaphore.");
-                app.in_flight[i] = app
-                    .device
-                    .create_fence(&fence_create_info, None)
-                    .expect("Failed to create fence.");
+            let transfer_pool_info = vk::CommandPoolCreateInfo::default()
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                .queue_family_index(families.transfer);
+            let transfer_pool = device
+                .create_command_pool(&transfer_pool_info, None)
+                .expect("Failed to create transfer command pool.");
+            (command_pool, transfer_pool)
+        };
+        let mut shared = Shared {
+            entry,
+            instance,
+            debug_messenger,
+            pdevice,
+            caps,
+            mem_props,
+            device,
+            queues,
+            surface_loader,
+            swap_device,
+            swap_config: self.config.swapchain,
+            depth_format,
+            render_pass: vk::RenderPass::null(),
+            debug_pass: vk::RenderPass::null(),
+            rendering,
+            command_pool,
+            transfer_pool,
+            sync,
+            frames_in_flight: self.config.frames_in_flight,
+        };
+        if !shared.rendering.is_dynamic() {
+            unsafe {
+                shared.create_scene_pass();
+                shared.create_debug_pass();
             }
         }
-        let make_buffer = |size, props, usage| unsafe {
-            let buff_info = vk::BufferCreateInfo::default()
-                .size(size)
-                .usage(usage)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE);
-            let buff = app
-                .device
-                .create_buffer(&buff_info, None)
-                .expect("Failed to create vertex buffer.");
-            let mem_reqs = app.device.get_buffer_memory_requirements(buff);
-            let mem_props = app
+        // timestamp queries
+        let gpu_profiler = unsafe {
+            let limits = shared
                 .instance
-                .get_physical_device_memory_properties(app.pdevice);
-            let mem_type = mem_props
-                .memory_types
-                .iter()
-                .enumerate()
-                .find(|(i, mt)| {
-                    (mem_reqs.memory_type_bits & (1 << i)) > 0
-                        && (mt.property_flags & props) == props
-                })
-                .expect("Failed to find suitable memory type.");
-            let alloc_info = vk::MemoryAllocateInfo::default()
-                .memory_type_index(mem_type.0 as u32)
-                .allocation_size(mem_reqs.size);
-            let memory = app
-                .device
-                .allocate_memory(&alloc_info, None)
-                .expect("Failed to allocate vertex buffer.");
-            app.device
-                .bind_buffer_memory(buff, memory, 0)
-                .expect("Failed to bind memory.");
-            (buff, memory)
+                .get_physical_device_properties(pdevice)
+                .limits;
+            let valid_bits = shared
+                .instance
+                .get_physical_device_queue_family_properties(pdevice)[families.graphics as usize]
+                .timestamp_valid_bits;
+            GpuProfiler::new(&limits, valid_bits)
         };
-        // buffers
-        unsafe {
-            let buff_size = (size_of::<Vertex>() * VERTICES.len()) as u64;
-            use vk::{BufferUsageFlags as buf, MemoryPropertyFlags as mpf};
-            let staging = make_buffer(
-                buff_size,
-                mpf::HOST_VISIBLE | mpf::HOST_COHERENT,
-                buf::TRANSFER_SRC,
-            );
-            let buff = app
-                .device
-                .map_memory(staging.1, 0, buff_size, vk::MemoryMapFlags::empty())
-                .expect("Failed to map vertex buff memory") as *mut Vertex;
-            std::slice::from_raw_parts_mut(buff, VERTICES.len()).copy_from_slice(&VERTICES);
-            app.vert_buff = make_buffer(
-                buff_size,
-                mpf::DEVICE_LOCAL,
-                buf::TRANSFER_DST | buf::VERTEX_BUFFER,
-            );
-            app.copy_buff(buff_size, staging.0, app.vert_buff.0);
-            app.device.destroy_buffer(staging.0, None);
-            app.device.free_memory(staging.1, None);
+        // sprites / text / debug lines
+        let (textures, sprite_renderer, text_renderer, debug_renderer) = unsafe {
+            let gpu = shared.gpu();
+            let mut textures = Textures::new(gpu);
+            let shader_module = load_shader_module(&shared.device);
+            let scene_target = shared.scene_target();
+            let sprite_renderer = SpriteRenderer::new(gpu, shader_module, scene_target, &textures);
+            let text_renderer = TextRenderer::new(gpu, shader_module, scene_target, &mut textures);
+            let debug_renderer = DebugRenderer::new(gpu, shader_module, shared.debug_target());
+            shared.device.destroy_shader_module(shader_module, None);
+            (textures, sprite_renderer, text_renderer, debug_renderer)
+        };
+        let main = window.id();
+        let main_window = unsafe {
+            AppWindow::new(
+                &shared,
+                window,
+                surface,
+                self.output_mode,
+                &textures,
+                &gpu_profiler,
+            )
+        };
+        let mut app = App {
+            shared,
+            windows: HashMap::from([(main, main_window)]),
+            main,
+            output_mode: self.output_mode,
+            textures,
+            sprite_renderer,
+            fonts: Fonts::default(),
+            text_renderer,
+            debug_renderer,
+            input: Input::default(),
+            clock: Clock::default(),
+            gpu_profiler,
+            stats: FrameStats::default(),
+            capture: Capture::new(self.capture_config),
+            opened: Vec::new(),
+            closed: Vec::new(),
+            displays: Vec::new(),
+            swapchain: None,
+            requested_output: None,
+        };
+        self.game.init(&mut app.context(event_loop, main));
+        unsafe { app.apply_window_requests(event_loop) };
+        self.app = Some(app);
+    }
 
-            let buff_size = (size_of::<u16>() * INDICES.len()) as u64;
-            let staging = make_buffer(
-                buff_size,
-                mpf::HOST_VISIBLE | mpf::HOST_COHERENT,
-                buf::TRANSFER_SRC,
-            );
-            let buff = app
-                .device
-                .map_memory(staging.1, 0, buff_size, vk::MemoryMapFlags::empty())
-                .expect("Failed to map vertex buff memory") as *mut u16;
-            std::slice::from_raw_parts_mut(buff, INDICES.len()).copy_from_slice(&INDICES);
-            app.ind_buff = make_buffer(
-                buff_size,
-                mpf::DEVICE_LOCAL,
-                buf::TRANSFER_DST | buf::INDEX_BUFFER,
-            );
-            app.copy_buff(buff_size, staging.0, app.ind_buff.0);
-            app.device.destroy_buffer(staging.0, None);
-            app.device.free_memory(staging.1, None);
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, id: WindowId, event: WindowEvent) {
+        let Some(ref mut app) = self.app else {
+            trace!(?event, "Ignored event without a window.");
+            return;
+        };
+        let Some(win) = app.windows.get_mut(&id) else {
+            trace!(window = ?id, ?event, "Ignored event for a closed window.");
+            return;
+        };
+        // egui needs to see every event, including the ones handled below. Input it consumed, like
+        // a click on a panel, is kept from the game.
+        let response = win.egui_state.on_window_event(&win.window, &event);
+        let for_ui = response.consumed;
+        match event {
+            WindowEvent::Destroyed | WindowEvent::CloseRequested => app.closed.push(id),
+            WindowEvent::RedrawRequested => unsafe { app.frame(event_loop, id, &mut self.game) },
+            WindowEvent::Resized(size) => {
+                // a minimized window renders nothing, so it won't notice the resize itself
+                if !win.minimized {
+                    win.resized = Some(size);
+                } else if size.width > 0 && size.height > 0 {
+                    unsafe { win.recreate_swapchain(&app.shared) };
+                }
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                debug!(window = ?id, scale_factor, "Scale factor changed");
+                // the physical size changes with it, which surfaces sized by the swapchain don't
+                // report, and egui lays out the next frame at the new scale
+                if !win.minimized {
+                    win.resized = Some(win.window.inner_size());
+                }
+            }
+            _ => {
+                if !for_ui || must_track(&event) {
+                    app.input.handle_event(id, &event);
+                }
+                if app.clock.redraw_mode == RedrawMode::OnDemand {
+                    win.window.request_redraw();
+                }
+            }
+        }
+        if !for_ui && app.windows.contains_key(&id) {
+            self.game.event(&mut app.context(event_loop, id), &event);
         }
-        *self = WrappedApp(Some(app));
+        unsafe { app.apply_window_requests(event_loop) };
     }
 
-    fn window_event(
-        &mut self,
-        event_loop: &ActiveEventLoop,
-        _: winit::window::WindowId,
-        event: WindowEvent,
-    ) {
-        if let Some(ref mut app) = self.0 {
-            match event {
-                WindowEvent::Destroyed | WindowEvent::CloseRequested => {
-                    unsafe { app.device.device_wait_idle().unwrap() };
-                    event_loop.exit()
+    /// Requests the next frame, or waits for it, according to the redraw mode and FPS cap.
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(ref app) = self.app else {
+            return;
+        };
+        if app.windows.values().all(|win| win.minimized) {
+            event_loop.set_control_flow(ControlFlow::Wait);
+            return;
+        }
+        match app.clock.redraw_mode {
+            RedrawMode::Continuous => match app.clock.next_frame() {
+                Some(at) if at > Instant::now() => {
+                    event_loop.set_control_flow(ControlFlow::WaitUntil(at))
                 }
-                WindowEvent::RedrawRequested => unsafe { app.render() },
-                WindowEvent::Resized(size) => app.resized = Some(size),
-                _ => println!("Unhandled event {:?}", event),
-            }
-        } else {
-            println!("None window received and ignored event {:?}.", event);
+                _ => {
+                    event_loop.set_control_flow(ControlFlow::Poll);
+                    for win in app.windows.values() {
+                        win.window.request_redraw();
+                    }
+                }
+            },
+            RedrawMode::OnDemand => event_loop.set_control_flow(ControlFlow::Wait),
         }
     }
 }
@@ -808,23 +974,28 @@ impl ApplicationHandler for WrappedApp {
 impl Drop for App {
     fn drop(&mut self) {
         unsafe {
-            for i in 0..MAX_IN_FLIGHT {
-                self.device.destroy_semaphore(self.render_done[i], None);
-                self.device.destroy_semaphore(self.image_available[i], None);
-                self.device.destroy_fence(self.in_flight[i], None);
+            let shared = &self.shared;
+            shared.device.device_wait_idle().unwrap();
+            for win in self.windows.values_mut() {
+                win.destroy(shared);
             }
-            self.clean_swapchain();
-            self.device.destroy_pipeline(self.pipeline, None);
-            self.device
-                .destroy_pipeline_layout(self.pipeline_layout, None);
-            self.device.destroy_buffer(self.vert_buff.0, None);
-            self.device.free_memory(self.vert_buff.1, None);
-            self.device.destroy_buffer(self.ind_buff.0, None);
-            self.device.free_memory(self.ind_buff.1, None);
-            self.device.destroy_command_pool(self.command_pool, None);
-            self.device.destroy_device(None);
-            self.surface_loader.destroy_surface(self.surface, None);
-            self.instance.destroy_instance(None);
+            shared.sync.destroy(&shared.device);
+            shared.device.destroy_render_pass(shared.render_pass, None);
+            shared.device.destroy_render_pass(shared.debug_pass, None);
+            self.sprite_renderer.destroy(&shared.device);
+            self.text_renderer.destroy(&shared.device);
+            self.debug_renderer.destroy(&shared.device);
+            self.textures.destroy(&shared.device);
+            shared
+                .device
+                .destroy_command_pool(shared.command_pool, None);
+            shared
+                .device
+                .destroy_command_pool(shared.transfer_pool, None);
+            label::clear();
+            shared.device.destroy_device(None);
+            shared.debug_messenger.destroy();
+            shared.instance.destroy_instance(None);
         }
     }
 }
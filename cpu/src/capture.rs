@@ -0,0 +1,121 @@
+//! Programmatic RenderDoc captures through its in-application API. The API is only there when the
+//! app was launched from RenderDoc, or had it injected; otherwise everything here does nothing.
+use crate::input::KeyCode;
+use renderdoc::{RenderDoc, WindowHandle, V141};
+use std::{ffi::c_void, ptr};
+use tracing::{info, warn};
+use winit::{
+    raw_window_handle::{HasWindowHandle, RawWindowHandle},
+    window::Window,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptureConfig {
+    /// Captures `frames` frames when pressed. RenderDoc's own capture keys keep working too.
+    pub hotkey: Option<KeyCode>,
+    pub frames: u32,
+    /// Opens the replay UI, connected to the app, after a capture, unless it is connected already.
+    pub open_replay_ui: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            hotkey: Some(KeyCode::F10),
+            frames: 1,
+            open_replay_ui: false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Capture {
+    renderdoc: Option<RenderDoc<V141>>,
+    pub config: CaptureConfig,
+    /// Frames left to capture.
+    pending: u32,
+    capturing: bool,
+}
+
+impl Capture {
+    pub fn new(config: CaptureConfig) -> Self {
+        // only finds RenderDoc if it is loaded already, never loads it
+        let renderdoc = RenderDoc::new().ok();
+        if renderdoc.is_some() {
+            info!("RenderDoc is attached, captures are available.");
+        }
+        Capture {
+            renderdoc,
+            config,
+            pending: 0,
+            capturing: false,
+        }
+    }
+
+    pub fn available(&self) -> bool {
+        self.renderdoc.is_some()
+    }
+
+    /// Captures the next `frames` frames, each on its own.
+    pub fn request(&mut self, frames: u32) {
+        if self.renderdoc.is_some() {
+            self.pending += frames;
+        }
+    }
+
+    /// Starts capturing `window` if a capture is due. Call before anything of the frame is
+    /// recorded.
+    pub fn begin_frame(&mut self, window: &Window) {
+        let Some(renderdoc) = &mut self.renderdoc else {
+            return;
+        };
+        if self.pending > 0 {
+            // a null device matches the only one there is, the window picks out the swapchain
+            renderdoc.start_frame_capture(ptr::null::<c_void>(), native_handle(window));
+            self.capturing = true;
+        }
+    }
+
+    /// Finishes the frame's capture of `window`, if any. Call after the frame is presented.
+    pub fn end_frame(&mut self, window: &Window) {
+        let Some(renderdoc) = &mut self.renderdoc else {
+            return;
+        };
+        if !self.capturing {
+            return;
+        }
+        renderdoc.end_frame_capture(ptr::null::<c_void>(), native_handle(window));
+        self.capturing = false;
+        self.pending -= 1;
+        if let Some((path, _)) = renderdoc
+            .get_num_captures()
+            .checked_sub(1)
+            .and_then(|last| renderdoc.get_capture(last))
+        {
+            info!(path = %path.display(), "Captured frame");
+        }
+        if self.pending == 0
+            && self.config.open_replay_ui
+            && !renderdoc.is_target_control_connected()
+        {
+            if let Err(err) = renderdoc.launch_replay_ui(true, None) {
+                warn!("Failed to open the RenderDoc replay UI: {err}");
+            }
+        }
+    }
+}
+
+/// The platform's handle for `window`, the way RenderDoc identifies windows. Null, which matches
+/// any window, where there is none.
+fn native_handle(window: &Window) -> WindowHandle {
+    let Ok(handle) = window.window_handle() else {
+        return ptr::null();
+    };
+    match handle.as_raw() {
+        RawWindowHandle::Xlib(h) => h.window as WindowHandle,
+        RawWindowHandle::Xcb(h) => h.window.get() as usize as WindowHandle,
+        RawWindowHandle::Wayland(h) => h.surface.as_ptr(),
+        RawWindowHandle::Win32(h) => h.hwnd.get() as WindowHandle,
+        _ => ptr::null(),
+    }
+}
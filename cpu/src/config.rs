@@ -0,0 +1,326 @@
+//! Startup settings that can be loaded from a TOML file. Every field is optional in the file, and
+//! anything left out keeps its default.
+use crate::{
+    device::GpuSelector,
+    display::{DisplayConfig, WindowMode},
+    frame::DEFAULT_FRAMES_IN_FLIGHT,
+    swapchain::{PresentMode, SwapchainConfig},
+    validation::ValidationConfig,
+};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// More would only add latency; the GPU can't be that far behind usefully.
+pub const MAX_FRAMES_IN_FLIGHT: usize = 8;
+
+/// The options `AppConfig::apply_args` understands, for a game's help text.
+pub const ARGS_USAGE: &str = "\
+  --config <PATH>            Load settings from a TOML file, which the options below override
+  --width <PIXELS>           Window width
+  --height <PIXELS>          Window height
+  --title <TITLE>            Window title
+  --window-mode <MODE>       windowed, borderless or exclusive
+  --monitor <SELECTOR>       Monitor index or part of its name, for fullscreen
+  --app-name <NAME>          Application name reported to the driver
+  --validation               Enable Vulkan validation
+  --no-validation            Disable Vulkan validation
+  --present-mode <MODE>      fifo, fifo-relaxed, mailbox or immediate
+  --frames-in-flight <N>     Frames the CPU may record ahead of the GPU
+  --gpu <SELECTOR>           GPU index, UUID or part of its name";
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    /// Inner size in logical pixels.
+    pub width: u32,
+    pub height: u32,
+    pub title: String,
+    /// Windowed or fullscreen, and on which monitor. `width` and `height` only apply to windowed.
+    pub display: DisplayConfig,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            width: 800,
+            height: 600,
+            title: "gaem".to_owned(),
+            display: DisplayConfig::default(),
+        }
+    }
+}
+
+/// Everything that is fixed once the window is created. Passed to `WrappedApp::with_config`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub window: WindowConfig,
+    /// Reported to the driver, which may apply per-application settings by it.
+    pub app_name: String,
+    pub validation: ValidationConfig,
+    pub swapchain: SwapchainConfig,
+    pub frames_in_flight: usize,
+    pub gpu: Option<GpuSelector>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            window: WindowConfig::default(),
+            app_name: "gaem".to_owned(),
+            validation: ValidationConfig::default(),
+            swapchain: SwapchainConfig::default(),
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            gpu: None,
+        }
+    }
+}
+
+impl AppConfig {
+    /// Reads and validates the config at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.into(), err))?;
+        Self::parse(&text, path)
+    }
+
+    /// Parses and validates `text`, read from `path`.
+    fn parse(text: &str, path: &Path) -> Result<Self, ConfigError> {
+        let config: AppConfig =
+            toml::from_str(text).map_err(|err| ConfigError::Parse(path.into(), err))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Applies command line options, as listed in `ARGS_USAGE`, and validates the result. A
+    /// `--config` file replaces everything first, wherever it is given; the other options then
+    /// override it in order.
+    pub fn apply_args(
+        &mut self,
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(), ConfigError> {
+        let args: Vec<_> = args.into_iter().collect();
+        if let Some(i) = args.iter().position(|a| a == "--config") {
+            let path = args
+                .get(i + 1)
+                .ok_or_else(|| ConfigError::Args("--config needs a value".to_owned()))?;
+            *self = AppConfig::load(path)?;
+        }
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| ConfigError::Args(format!("{arg} needs a value")))
+            };
+            match arg.as_str() {
+                "--config" => {
+                    value()?;
+                }
+                "--width" => self.window.width = parse_arg(&arg, value()?)?,
+                "--height" => self.window.height = parse_arg(&arg, value()?)?,
+                "--title" => self.window.title = value()?,
+                "--window-mode" => {
+                    self.window.display.mode = parse_arg::<WindowMode>(&arg, value()?)?
+                }
+                "--monitor" => self.window.display.monitor = Some(value()?.into()),
+                "--app-name" => self.app_name = value()?,
+                "--validation" => self.validation.enabled = true,
+                "--no-validation" => self.validation.enabled = false,
+                "--present-mode" => {
+                    self.swapchain.present_mode = parse_arg::<PresentMode>(&arg, value()?)?;
+                }
+                "--frames-in-flight" => self.frames_in_flight = parse_arg(&arg, value()?)?,
+                "--gpu" => self.gpu = Some(value()?.into()),
+                _ => return Err(ConfigError::Args(format!("unknown option {arg:?}"))),
+            }
+        }
+        self.validate()
+    }
+
+    /// Checks what the types don't, to fail before the window is created rather than after.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+        if self.window.width == 0 || self.window.height == 0 {
+            return invalid(format!(
+                "window size {}x{} must not be empty",
+                self.window.width, self.window.height
+            ));
+        }
+        if self.app_name.contains('\0') {
+            return invalid("app_name must not contain NUL characters".to_owned());
+        }
+        if !(1..=MAX_FRAMES_IN_FLIGHT).contains(&self.frames_in_flight) {
+            return invalid(format!(
+                "frames_in_flight is {}, but must be from 1 to {MAX_FRAMES_IN_FLIGHT}",
+                self.frames_in_flight
+            ));
+        }
+        if self.swapchain.image_count == Some(0) {
+            return invalid("swapchain.image_count must be at least 1".to_owned());
+        }
+        Ok(())
+    }
+}
+
+fn parse_arg<T: FromStr>(arg: &str, value: String) -> Result<T, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|err| ConfigError::Args(format!("{arg} got {value:?}: {err}")))
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// A command line option that is unknown, lacks its value or has one that doesn't parse.
+    Args(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "Failed to read {}: {err}", path.display()),
+            ConfigError::Parse(path, err) => write!(f, "Invalid config {}: {err}", path.display()),
+            ConfigError::Args(msg) => write!(f, "Invalid option: {msg}"),
+            ConfigError::Invalid(msg) => write!(f, "Invalid config: {msg}"),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(_, err) => Some(err),
+            ConfigError::Parse(_, err) => Some(err),
+            ConfigError::Args(_) | ConfigError::Invalid(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn invalid(config: AppConfig) -> String {
+        match config.validate() {
+            Err(ConfigError::Invalid(msg)) => msg,
+            other => panic!("expected an invalid config, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn default_is_valid() {
+        AppConfig::default().validate().unwrap();
+    }
+
+    #[test]
+    fn validate_rejects_what_types_allow() {
+        let mut config = AppConfig::default();
+        config.window.height = 0;
+        assert!(invalid(config).contains("800x0"));
+
+        let config = AppConfig {
+            app_name: "ga\0em".to_owned(),
+            ..Default::default()
+        };
+        assert!(invalid(config).contains("app_name"));
+
+        for frames_in_flight in [0, MAX_FRAMES_IN_FLIGHT + 1] {
+            let config = AppConfig {
+                frames_in_flight,
+                ..Default::default()
+            };
+            assert!(invalid(config).contains("frames_in_flight"));
+        }
+
+        let mut config = AppConfig::default();
+        config.swapchain.image_count = Some(0);
+        assert!(invalid(config).contains("image_count"));
+    }
+
+    #[test]
+    fn unknown_fields_are_errors() {
+        for text in [
+            "widht = 3",
+            "[window]\ntitel = \"x\"",
+            "[swapchain]\nmode = \"fifo\"",
+        ] {
+            let err = AppConfig::parse(text, Path::new("gaem.toml")).unwrap_err();
+            let ConfigError::Parse(path, _) = &err else {
+                panic!("expected a parse error, got {err:?}");
+            };
+            assert_eq!(path, Path::new("gaem.toml"));
+            assert!(err.to_string().contains("unknown field"), "{err}");
+        }
+    }
+
+    #[test]
+    fn file_values_are_validated() {
+        let err = AppConfig::parse("frames_in_flight = 0", Path::new("gaem.toml")).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid(_)), "{err:?}");
+    }
+
+    #[test]
+    fn args_override_in_order() {
+        let mut config = AppConfig::default();
+        config
+            .apply_args(args(&[
+                "--width",
+                "1280",
+                "--present-mode",
+                "immediate",
+                "--validation",
+                "--no-validation",
+                "--window-mode",
+                "borderless",
+            ]))
+            .unwrap();
+        assert_eq!(config.window.width, 1280);
+        assert_eq!(config.swapchain.present_mode, PresentMode::Immediate);
+        assert!(!config.validation.enabled);
+        assert_eq!(config.window.display.mode, WindowMode::Borderless);
+    }
+
+    #[test]
+    fn bad_args_are_errors() {
+        for (bad, expected) in [
+            (&["--width"][..], "--width needs a value"),
+            (&["--frobnicate"], "unknown option \"--frobnicate\""),
+            (&["--height", "tall"], "--height got \"tall\""),
+            (&["--present-mode", "vsync"], "unknown present mode"),
+        ] {
+            let err = AppConfig::default().apply_args(args(bad)).unwrap_err();
+            assert!(matches!(err, ConfigError::Args(_)), "{err:?}");
+            assert!(err.to_string().contains(expected), "{err}");
+        }
+    }
+
+    #[test]
+    fn args_are_validated() {
+        let err = AppConfig::default()
+            .apply_args(args(&["--frames-in-flight", "0"]))
+            .unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid(_)), "{err:?}");
+    }
+
+    #[test]
+    fn missing_config_file_is_an_io_error() {
+        let err = AppConfig::default()
+            .apply_args(args(&["--config", "/nonexistent/gaem.toml"]))
+            .unwrap_err();
+        assert!(matches!(err, ConfigError::Io(..)), "{err:?}");
+    }
+}
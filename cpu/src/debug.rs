@@ -0,0 +1,301 @@
+//! Immediate mode debug drawing. The `debug_*` functions can be called from anywhere, on any
+//! thread, and queue line segments that are drawn in their own pass after the scene, over
+//! everything in it. Nothing in the scene writes depth, so lines are never hidden.
+use crate::{
+    frame::{FrameContext, FrameData},
+    pipeline::{create_graphics_pipeline, Blend, PassTarget, PipelineDesc},
+    util::{DynBuffer, Gpu},
+};
+use ash::{vk, Device};
+use glam::{vec3, Mat4, Vec3, Vec4};
+use std::{f32::consts::TAU, sync::Mutex, time::Instant};
+
+const SPHERE_SEGMENTS: usize = 32;
+
+#[derive(Clone, Copy)]
+struct DebugLine {
+    a: Vec3,
+    b: Vec3,
+    color: Vec4,
+    /// Seconds left before the line is removed. Lines with no time left are still drawn once.
+    lifetime: f32,
+}
+
+static LINES: Mutex<Vec<DebugLine>> = Mutex::new(Vec::new());
+
+/// The lines of a single `debug_*` call. They are queued once the shape is dropped, at the end
+/// of the statement unless it is kept around, so options set on it apply to its lines only. By
+/// default they are only drawn for the current frame.
+pub struct DebugShape {
+    lines: Vec<DebugLine>,
+    lifetime: f32,
+}
+
+impl DebugShape {
+    fn push(lines: impl IntoIterator<Item = (Vec3, Vec3, Vec4)>) -> Self {
+        DebugShape {
+            lines: lines
+                .into_iter()
+                .map(|(a, b, color)| DebugLine {
+                    a,
+                    b,
+                    color,
+                    lifetime: 0.0,
+                })
+                .collect(),
+            lifetime: 0.0,
+        }
+    }
+
+    /// Keeps the shape on screen for `seconds` instead of a single frame.
+    pub fn lifetime(mut self, seconds: f32) -> Self {
+        self.lifetime = seconds;
+        self
+    }
+}
+
+impl Drop for DebugShape {
+    fn drop(&mut self) {
+        let lifetime = self.lifetime;
+        LINES
+            .lock()
+            .unwrap()
+            .extend(self.lines.drain(..).map(|l| DebugLine { lifetime, ..l }));
+    }
+}
+
+pub fn debug_line(a: Vec3, b: Vec3, color: Vec4) -> DebugShape {
+    DebugShape::push([(a, b, color)])
+}
+
+/// An axis aligned box.
+pub fn debug_box(center: Vec3, half_extents: Vec3, color: Vec4) -> DebugShape {
+    let corner = |i: usize| {
+        center
+            + half_extents
+                * vec3(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { -1.0 } else { 1.0 },
+                )
+    };
+    // every pair of corners differing in exactly one axis is an edge
+    let edges = (0..8).flat_map(move |i| {
+        [1, 2, 4]
+            .into_iter()
+            .filter(move |bit| i & bit == 0)
+            .map(move |bit| (corner(i), corner(i | bit), color))
+    });
+    DebugShape::push(edges)
+}
+
+/// A wireframe sphere made of three circles, one around each axis.
+pub fn debug_sphere(center: Vec3, radius: f32, color: Vec4) -> DebugShape {
+    let point = |axis: usize, i: usize| {
+        let (sin, cos) = (i as f32 / SPHERE_SEGMENTS as f32 * TAU).sin_cos();
+        let circle = [
+            vec3(0.0, cos, sin),
+            vec3(cos, 0.0, sin),
+            vec3(cos, sin, 0.0),
+        ];
+        center + circle[axis] * radius
+    };
+    let lines = (0..3).flat_map(move |axis| {
+        (0..SPHERE_SEGMENTS).map(move |i| (point(axis, i), point(axis, i + 1), color))
+    });
+    DebugShape::push(lines)
+}
+
+/// A grid on the XZ plane with `cells` cells of `cell_size` along each side.
+pub fn debug_grid(center: Vec3, cell_size: f32, cells: u32, color: Vec4) -> DebugShape {
+    let half = cell_size * cells as f32 / 2.0;
+    let lines = (0..=cells).flat_map(move |i| {
+        let offset = i as f32 * cell_size - half;
+        [
+            (
+                center + vec3(offset, 0.0, -half),
+                center + vec3(offset, 0.0, half),
+                color,
+            ),
+            (
+                center + vec3(-half, 0.0, offset),
+                center + vec3(half, 0.0, offset),
+                color,
+            ),
+        ]
+    });
+    DebugShape::push(lines)
+}
+
+/// The X, Y and Z axes of `transform` in red, green and blue, each `size` long.
+pub fn debug_axes(transform: Mat4, size: f32) -> DebugShape {
+    let origin = transform.transform_point3(Vec3::ZERO);
+    let axes = [
+        (Vec3::X, Vec4::new(1.0, 0.0, 0.0, 1.0)),
+        (Vec3::Y, Vec4::new(0.0, 1.0, 0.0, 1.0)),
+        (Vec3::Z, Vec4::new(0.0, 0.0, 1.0, 1.0)),
+    ];
+    DebugShape::push(
+        axes.map(|(axis, color)| (origin, transform.transform_point3(axis * size), color)),
+    )
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct DebugVertex {
+    pos: Vec3,
+    col: Vec4,
+}
+
+impl DebugVertex {
+    fn binding_descr() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription::default()
+            .stride(size_of::<DebugVertex>() as u32)
+            .binding(0)
+            .input_rate(vk::VertexInputRate::VERTEX)]
+    }
+
+    fn attr_descr() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription::default()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(core::mem::offset_of!(DebugVertex, pos) as u32),
+            vk::VertexInputAttributeDescription::default()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(core::mem::offset_of!(DebugVertex, col) as u32),
+        ]
+    }
+}
+
+/// The vertex buffer each frame in flight writes its lines to.
+struct DebugFrame {
+    vert_buff: DynBuffer,
+}
+
+impl FrameData for DebugFrame {
+    unsafe fn destroy(&mut self, device: &Device) {
+        self.vert_buff.destroy(device);
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct DebugRenderer {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    vertices: Vec<DebugVertex>,
+    last_flush: Option<Instant>,
+}
+
+impl DebugRenderer {
+    pub unsafe fn new(gpu: Gpu, shader_module: vk::ShaderModule, target: PassTarget) -> Self {
+        let device = gpu.device;
+        let push_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .size(size_of::<Mat4>() as u32)];
+        let layout_info =
+            vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&push_ranges);
+        let pipeline_layout = device
+            .create_pipeline_layout(&layout_info, None)
+            .expect("Failed to create debug pipeline layout.");
+        let pipeline = create_graphics_pipeline(
+            device,
+            shader_module,
+            target,
+            pipeline_layout,
+            &PipelineDesc {
+                name: "debug lines",
+                vert: c"debug_vert",
+                frag: c"debug_frag",
+                bindings: &DebugVertex::binding_descr(),
+                attrs: &DebugVertex::attr_descr(),
+                topology: vk::PrimitiveTopology::LINE_LIST,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_test: false,
+                depth_write: false,
+                blend: Blend::Alpha,
+            },
+        );
+        DebugRenderer {
+            pipeline_layout,
+            pipeline,
+            vertices: Vec::new(),
+            last_flush: None,
+        }
+    }
+
+    /// Gives each of `frames` the vertex buffer it writes its lines to.
+    pub unsafe fn attach_frames(gpu: Gpu, frames: &mut [FrameContext]) {
+        for frame in frames.iter_mut() {
+            frame.attach(DebugFrame {
+                vert_buff: DynBuffer::new(
+                    gpu,
+                    4096 * size_of::<DebugVertex>(),
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    "debug line vertices",
+                ),
+            });
+        }
+    }
+
+    /// Draws every queued line, then ages them and drops the ones whose lifetime ran out. Must be
+    /// called inside the debug pass.
+    pub unsafe fn flush(&mut self, gpu: Gpu, frame: &mut FrameContext, view_proj: Mat4) {
+        let now = Instant::now();
+        let dt = self
+            .last_flush
+            .replace(now)
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        let mut lines = LINES.lock().unwrap();
+        self.vertices.clear();
+        self.vertices.extend(lines.iter().flat_map(|l| {
+            [
+                DebugVertex {
+                    pos: l.a,
+                    col: l.color,
+                },
+                DebugVertex {
+                    pos: l.b,
+                    col: l.color,
+                },
+            ]
+        }));
+        lines.retain_mut(|l| {
+            l.lifetime -= dt;
+            l.lifetime > 0.0
+        });
+        drop(lines);
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        let device = gpu.device;
+        let cmd = frame.cmd;
+        let vert_buff = &mut frame.get_mut::<DebugFrame>().vert_buff;
+        vert_buff.write(gpu, &self.vertices);
+        device.cmd_bind_vertex_buffers(cmd, 0, &[vert_buff.buff], &[0]);
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_push_constants(
+            cmd,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            bytemuck::cast_slice(&view_proj.to_cols_array()),
+        );
+        device.cmd_draw(cmd, self.vertices.len() as u32, 1, 0, 0);
+    }
+
+    /// Drops the lines only meant for a frame that is skipped, instead of drawing them along with
+    /// the next frame's.
+    pub fn skip_frame(&self) {
+        LINES.lock().unwrap().retain(|l| l.lifetime > 0.0);
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+    }
+}
@@ -0,0 +1,319 @@
+//! Physical device selection. Every device that can render to the window is scored, by type first,
+//! then the optional features it supports, then its video memory, and the best one is used unless
+//! another is picked with `WrappedApp::set_gpu` or the `GAEM_GPU` environment variable.
+use crate::features::{self, DeviceCapabilities};
+use ash::{khr::surface, vk, Device, Instance};
+use serde::Deserialize;
+use std::{convert::Infallible, env, fmt, str::FromStr};
+use tracing::{info, warn};
+
+/// Overrides the GPU selection, in the format `GpuSelector` is parsed from.
+pub const GPU_ENV: &str = "GAEM_GPU";
+
+/// Picks a GPU explicitly instead of the best scoring one. Config files give it as a string.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(from = "String")]
+pub enum GpuSelector {
+    /// Position in Vulkan's enumeration order, as logged at startup.
+    Index(usize),
+    Uuid([u8; vk::UUID_SIZE]),
+    /// Case insensitive part of the device name.
+    Name(String),
+}
+
+impl GpuSelector {
+    fn matches(&self, index: usize, name: &str, uuid: Option<[u8; vk::UUID_SIZE]>) -> bool {
+        match self {
+            GpuSelector::Index(i) => *i == index,
+            GpuSelector::Uuid(u) => uuid == Some(*u),
+            GpuSelector::Name(n) => name.to_lowercase().contains(&n.to_lowercase()),
+        }
+    }
+}
+
+impl FromStr for GpuSelector {
+    type Err = Infallible;
+
+    /// Numbers are indices and 32 hex digits, dashes allowed, are UUIDs. Anything else is a name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(index) = s.parse() {
+            return Ok(GpuSelector::Index(index));
+        }
+        let hex: String = s.chars().filter(|c| *c != '-').collect();
+        if hex.len() == 2 * vk::UUID_SIZE && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            let mut uuid = [0; vk::UUID_SIZE];
+            for (i, byte) in uuid.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+            }
+            return Ok(GpuSelector::Uuid(uuid));
+        }
+        Ok(GpuSelector::Name(s.to_owned()))
+    }
+}
+
+impl From<String> for GpuSelector {
+    fn from(s: String) -> Self {
+        let Ok(selector) = s.parse();
+        selector
+    }
+}
+
+impl fmt::Display for GpuSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuSelector::Index(i) => write!(f, "index {i}"),
+            GpuSelector::Uuid(u) => write!(f, "UUID {}", format_uuid(u)),
+            GpuSelector::Name(n) => write!(f, "name \"{n}\""),
+        }
+    }
+}
+
+fn format_uuid(uuid: &[u8; vk::UUID_SIZE]) -> String {
+    let hex: String = uuid.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Only available with Vulkan 1.1, on both the instance and the device.
+unsafe fn device_uuid(
+    instance: &Instance,
+    api_version: u32,
+    pdevice: vk::PhysicalDevice,
+    props: &vk::PhysicalDeviceProperties,
+) -> Option<[u8; vk::UUID_SIZE]> {
+    if api_version.min(props.api_version) < vk::API_VERSION_1_1 {
+        return None;
+    }
+    let mut id_props = vk::PhysicalDeviceIDProperties::default();
+    {
+        let mut props2 = vk::PhysicalDeviceProperties2::default().push_next(&mut id_props);
+        instance.get_physical_device_properties2(pdevice, &mut props2);
+    }
+    Some(id_props.device_uuid)
+}
+
+/// The queue family used for each kind of work. Any of them may be the same family.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct QueueFamilies {
+    pub graphics: u32,
+    pub present: u32,
+    /// A family without graphics if there is one, so compute work can run alongside rendering.
+    pub compute: u32,
+    /// A family with only transfers if there is one, usually the GPU's copy engine.
+    pub transfer: u32,
+}
+
+impl QueueFamilies {
+    /// The families to use on `pdevice`, or why it can't render to the window.
+    unsafe fn find(
+        instance: &Instance,
+        surface_loader: &surface::Instance,
+        surface: vk::SurfaceKHR,
+        pdevice: vk::PhysicalDevice,
+    ) -> Result<Self, &'static str> {
+        let props = instance.get_physical_device_queue_family_properties(pdevice);
+        let has = |i: usize, flags| props[i].queue_flags.contains(flags);
+        let can_present = |i: usize| {
+            surface_loader
+                .get_physical_device_surface_support(pdevice, i as u32, surface)
+                .unwrap_or(false)
+        };
+        let families = 0..props.len();
+        // one family doing both avoids sharing the swapchain images
+        let graphics = families
+            .clone()
+            .find(|&i| has(i, vk::QueueFlags::GRAPHICS) && can_present(i))
+            .or_else(|| families.clone().find(|&i| has(i, vk::QueueFlags::GRAPHICS)))
+            .ok_or("no graphics queue family")?;
+        let present = if can_present(graphics) {
+            graphics
+        } else {
+            families
+                .clone()
+                .find(|&i| can_present(i))
+                .ok_or("no queue family can present to the window")?
+        };
+        let compute = families
+            .clone()
+            .find(|&i| has(i, vk::QueueFlags::COMPUTE) && !has(i, vk::QueueFlags::GRAPHICS))
+            .unwrap_or(graphics);
+        // graphics and compute families support transfers even if they don't report it
+        let transfer = families
+            .clone()
+            .find(|&i| {
+                has(i, vk::QueueFlags::TRANSFER)
+                    && !has(i, vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            })
+            .unwrap_or(graphics);
+        Ok(QueueFamilies {
+            graphics: graphics as u32,
+            present: present as u32,
+            compute: compute as u32,
+            transfer: transfer as u32,
+        })
+    }
+
+    /// Each family used once, to create a queue from.
+    pub fn unique(&self) -> Vec<u32> {
+        let mut families = vec![self.graphics, self.present, self.compute, self.transfer];
+        families.sort_unstable();
+        families.dedup();
+        families
+    }
+}
+
+/// The first queue of each family in `QueueFamilies`. Families used for several kinds of work
+/// share their queue.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Queues {
+    pub families: QueueFamilies,
+    pub graphics: vk::Queue,
+    pub present: vk::Queue,
+    /// Nothing runs asynchronous compute work yet.
+    #[allow(dead_code)]
+    pub compute: vk::Queue,
+    pub transfer: vk::Queue,
+}
+
+impl Queues {
+    /// `device` must have been created with a queue from each of `families.unique()`.
+    pub unsafe fn new(device: &Device, families: QueueFamilies) -> Self {
+        Queues {
+            families,
+            graphics: device.get_device_queue(families.graphics, 0),
+            present: device.get_device_queue(families.present, 0),
+            compute: device.get_device_queue(families.compute, 0),
+            transfer: device.get_device_queue(families.transfer, 0),
+        }
+    }
+}
+
+/// Why the device can't be used, if it can't.
+unsafe fn check_device(
+    instance: &Instance,
+    api_version: u32,
+    surface_loader: &surface::Instance,
+    surface: vk::SurfaceKHR,
+    pdevice: vk::PhysicalDevice,
+) -> Result<(DeviceCapabilities, QueueFamilies), String> {
+    let caps = features::negotiate(instance, api_version, pdevice)?;
+    let families = QueueFamilies::find(instance, surface_loader, surface, pdevice)?;
+    // only query support after verifying extensions
+    let formats = surface_loader
+        .get_physical_device_surface_formats(pdevice, surface)
+        .unwrap_or_default();
+    let modes = surface_loader
+        .get_physical_device_surface_present_modes(pdevice, surface)
+        .unwrap_or_default();
+    if formats.is_empty() || modes.is_empty() {
+        return Err("no surface formats or present modes".to_owned());
+    }
+    Ok((caps, families))
+}
+
+/// A usable device's score, handle, capabilities and queue families.
+type Candidate = (
+    (u32, u32, u64),
+    vk::PhysicalDevice,
+    DeviceCapabilities,
+    QueueFamilies,
+);
+
+/// Compared in order: device type, optional features and device local memory in MiB.
+unsafe fn score(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    props: &vk::PhysicalDeviceProperties,
+    caps: &DeviceCapabilities,
+) -> (u32, u32, u64) {
+    let device_type = match props.device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 1,
+        _ => 0,
+    };
+    let mem_props = instance.get_physical_device_memory_properties(pdevice);
+    let memory = mem_props
+        .memory_heaps_as_slice()
+        .iter()
+        .filter(|h| h.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|h| h.size >> 20)
+        .sum();
+    (device_type, caps.optional_count(), memory)
+}
+
+/// Returns the device to use, what it supports and the queue families to use on it. `GAEM_GPU` takes
+/// precedence over `selector`; if neither matches a usable device the best one is used.
+pub(crate) unsafe fn select_device(
+    instance: &Instance,
+    api_version: u32,
+    surface_loader: &surface::Instance,
+    surface: vk::SurfaceKHR,
+    selector: Option<&GpuSelector>,
+) -> (vk::PhysicalDevice, DeviceCapabilities, QueueFamilies) {
+    let selector = env::var(GPU_ENV)
+        .ok()
+        .map(|s| s.parse().unwrap())
+        .or_else(|| selector.cloned());
+    let mut best: Option<Candidate> = None;
+    let mut best_selected: Option<Candidate> = None;
+    let devices = instance
+        .enumerate_physical_devices()
+        .expect("Failed to enumerate devices.");
+    for (index, &pdevice) in devices.iter().enumerate() {
+        let props = instance.get_physical_device_properties(pdevice);
+        let name = props
+            .device_name_as_c_str()
+            .map_or("unknown".into(), |n| n.to_string_lossy());
+        let uuid = device_uuid(instance, api_version, pdevice, &props);
+        let desc = format!(
+            "GPU {index}: {name} ({:?}, UUID {})",
+            props.device_type,
+            uuid.as_ref().map_or("unknown".to_owned(), format_uuid)
+        );
+        let (caps, families) =
+            match check_device(instance, api_version, surface_loader, surface, pdevice) {
+                Ok(checked) => checked,
+                Err(reason) => {
+                    info!("{desc} rejected: {reason}");
+                    continue;
+                }
+            };
+        let score = score(instance, pdevice, &props, &caps);
+        // ties go to the device enumerated first
+        let candidate = (score, pdevice, caps, families);
+        if best.is_none_or(|(s, ..)| score > s) {
+            best = Some(candidate);
+        }
+        match &selector {
+            Some(selector) if !selector.matches(index, &name, uuid) => {
+                info!("{desc} rejected: doesn't match the GPU selection {selector}");
+            }
+            _ => {
+                info!("{desc} usable, score {score:?}");
+                if best_selected.is_none_or(|(s, ..)| score > s) {
+                    best_selected = Some(candidate);
+                }
+            }
+        }
+    }
+    if best_selected.is_none() && best.is_some() {
+        if let Some(selector) = &selector {
+            warn!("No usable GPU matches {selector}, falling back to the best one.");
+        }
+        best_selected = best;
+    }
+    let (_, pdevice, caps, families) = best_selected.expect("Failed to find suitable device");
+    let props = instance.get_physical_device_properties(pdevice);
+    let name = props.device_name_as_c_str().unwrap_or(c"unknown");
+    info!(device = ?name, ?caps, ?families, "Using GPU");
+    (pdevice, caps, families)
+}
@@ -0,0 +1,188 @@
+//! Window modes, and the monitor and video mode a fullscreen window uses.
+use serde::Deserialize;
+use std::{convert::Infallible, fmt, str::FromStr};
+use tracing::{info, warn};
+use winit::{
+    monitor::{MonitorHandle, VideoModeHandle},
+    window::Fullscreen,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WindowMode {
+    #[default]
+    Windowed,
+    /// A window covering the monitor at its desktop resolution. Switches instantly.
+    Borderless,
+    /// Takes over the monitor in the configured video mode. Switching makes the display go blank
+    /// for a moment.
+    Exclusive,
+}
+
+impl FromStr for WindowMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "windowed" => Ok(WindowMode::Windowed),
+            "borderless" => Ok(WindowMode::Borderless),
+            "exclusive" => Ok(WindowMode::Exclusive),
+            _ => Err(format!(
+                "unknown window mode {s:?}, expected windowed, borderless or exclusive"
+            )),
+        }
+    }
+}
+
+/// Picks a monitor explicitly instead of the one the window is on. Config files give it as a
+/// string.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(from = "String")]
+pub enum MonitorSelector {
+    /// Position in the order the platform lists monitors in, as logged when going fullscreen.
+    Index(usize),
+    /// Case insensitive part of the monitor name.
+    Name(String),
+}
+
+impl MonitorSelector {
+    fn matches(&self, index: usize, monitor: &MonitorHandle) -> bool {
+        match self {
+            MonitorSelector::Index(i) => *i == index,
+            MonitorSelector::Name(n) => monitor
+                .name()
+                .is_some_and(|name| name.to_lowercase().contains(&n.to_lowercase())),
+        }
+    }
+}
+
+impl FromStr for MonitorSelector {
+    type Err = Infallible;
+
+    /// Numbers are indices, anything else is a name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Ok(match s.parse() {
+            Ok(index) => MonitorSelector::Index(index),
+            Err(_) => MonitorSelector::Name(s.to_owned()),
+        })
+    }
+}
+
+impl From<String> for MonitorSelector {
+    fn from(s: String) -> Self {
+        let Ok(selector) = s.parse();
+        selector
+    }
+}
+
+impl fmt::Display for MonitorSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MonitorSelector::Index(i) => write!(f, "index {i}"),
+            MonitorSelector::Name(n) => write!(f, "name \"{n}\""),
+        }
+    }
+}
+
+/// The resolution, and optionally refresh rate, exclusive fullscreen should switch to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VideoModeRequest {
+    pub width: u32,
+    pub height: u32,
+    /// In hertz. The highest the monitor offers at the resolution if left out.
+    #[serde(default)]
+    pub refresh_rate: Option<u32>,
+}
+
+/// How a window covers the screen. Changed at runtime with `Context::set_display` or
+/// `WrappedApp::set_display`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    pub mode: WindowMode,
+    /// The monitor to go fullscreen on, otherwise the one the window is on.
+    pub monitor: Option<MonitorSelector>,
+    /// Only used by exclusive fullscreen. The monitor's largest mode if left out.
+    pub video_mode: Option<VideoModeRequest>,
+}
+
+impl DisplayConfig {
+    /// What to pass to winit, given the platform's `monitors` and the monitor the window is on.
+    /// Falls back to borderless if the monitor has no matching video mode, and to the current
+    /// monitor if none matches the selector.
+    pub(crate) fn fullscreen(
+        &self,
+        monitors: impl Iterator<Item = MonitorHandle>,
+        current: Option<MonitorHandle>,
+    ) -> Option<Fullscreen> {
+        if self.mode == WindowMode::Windowed {
+            return None;
+        }
+        let monitors: Vec<_> = monitors.collect();
+        for (i, monitor) in monitors.iter().enumerate() {
+            info!(
+                index = i,
+                name = monitor.name().as_deref().unwrap_or("unknown"),
+                size = ?monitor.size(),
+                scale_factor = monitor.scale_factor(),
+                "Monitor"
+            );
+        }
+        let monitor = match &self.monitor {
+            Some(selector) => {
+                let found = monitors
+                    .into_iter()
+                    .enumerate()
+                    .find(|(i, m)| selector.matches(*i, m));
+                if found.is_none() {
+                    warn!(%selector, "No monitor matches, using the window's.");
+                }
+                found.map(|(_, m)| m).or(current)
+            }
+            None => current,
+        };
+        if self.mode == WindowMode::Borderless {
+            return Some(Fullscreen::Borderless(monitor));
+        }
+        let mode = monitor.as_ref().and_then(|m| self.pick_video_mode(m));
+        match mode {
+            Some(mode) => {
+                info!(
+                    size = ?mode.size(),
+                    refresh_rate = mode.refresh_rate_millihertz() as f32 / 1000.0,
+                    bit_depth = mode.bit_depth(),
+                    "Using video mode"
+                );
+                Some(Fullscreen::Exclusive(mode))
+            }
+            None => {
+                warn!(request = ?self.video_mode, "No matching video mode, using borderless.");
+                Some(Fullscreen::Borderless(monitor))
+            }
+        }
+    }
+
+    /// The largest matching mode, preferring higher refresh rates and then bit depths.
+    fn pick_video_mode(&self, monitor: &MonitorHandle) -> Option<VideoModeHandle> {
+        let matches = |mode: &VideoModeHandle| {
+            let Some(request) = self.video_mode else {
+                return true;
+            };
+            let size = mode.size();
+            // monitors report rates like 59.94 Hz, which a request for 60 should still match
+            let rate = (mode.refresh_rate_millihertz() + 500) / 1000;
+            (size.width, size.height) == (request.width, request.height)
+                && request.refresh_rate.is_none_or(|r| r == rate)
+        };
+        monitor.video_modes().filter(matches).max_by_key(|mode| {
+            let size = mode.size();
+            (
+                size.width * size.height,
+                mode.refresh_rate_millihertz(),
+                mode.bit_depth(),
+            )
+        })
+    }
+}
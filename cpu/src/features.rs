@@ -0,0 +1,208 @@
+//! The device extensions and features the renderer uses, and which of them a device provides.
+//! Devices missing a required one are rejected; optional ones are enabled when available and
+//! reported in `DeviceCapabilities` so subsystems can fall back without them.
+use crate::device::QueueFamilies;
+use ash::{
+    khr::{dynamic_rendering, swapchain},
+    vk, Device, Instance,
+};
+use std::ffi::CStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Need {
+    Required,
+    Optional,
+}
+
+struct ExtensionRequest {
+    name: &'static CStr,
+    need: Need,
+    /// The lowest version the extension is used on, as its dependencies are core there.
+    min_version: u32,
+    /// The version it was promoted to core in, from which on it isn't enabled.
+    core_in: Option<u32>,
+}
+
+const EXTENSIONS: [ExtensionRequest; 2] = [
+    ExtensionRequest {
+        name: swapchain::NAME,
+        need: Need::Required,
+        min_version: vk::API_VERSION_1_0,
+        core_in: None,
+    },
+    ExtensionRequest {
+        name: dynamic_rendering::NAME,
+        need: Need::Optional,
+        min_version: vk::API_VERSION_1_2,
+        core_in: Some(vk::API_VERSION_1_3),
+    },
+];
+
+/// Features beyond core 1.0. None of core 1.0's are enabled, as the renderer uses none of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Feature {
+    TimelineSemaphore,
+    DynamicRendering,
+    Synchronization2,
+}
+
+const FEATURES: [(Feature, Need); 3] = [
+    (Feature::TimelineSemaphore, Need::Optional),
+    (Feature::DynamicRendering, Need::Optional),
+    (Feature::Synchronization2, Need::Optional),
+];
+
+/// What the device was created with, for subsystems to pick their code path by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceCapabilities {
+    /// The Vulkan version in use, the lower of the instance's and the device's.
+    pub api_version: u32,
+    pub timeline_semaphore: bool,
+    /// From core 1.3 or `VK_KHR_dynamic_rendering`.
+    pub dynamic_rendering: bool,
+    /// Only used along with timeline semaphores.
+    pub synchronization2: bool,
+    /// Whether dynamic rendering comes from the extension rather than core.
+    pub(crate) dynamic_rendering_khr: bool,
+}
+
+impl DeviceCapabilities {
+    /// How many of the optional features are available, for ranking devices.
+    pub(crate) fn optional_count(&self) -> u32 {
+        [
+            self.timeline_semaphore,
+            self.dynamic_rendering,
+            self.synchronization2,
+        ]
+        .into_iter()
+        .filter(|f| *f)
+        .count() as u32
+    }
+
+    fn has(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::TimelineSemaphore => self.timeline_semaphore,
+            Feature::DynamicRendering => self.dynamic_rendering,
+            Feature::Synchronization2 => self.synchronization2,
+        }
+    }
+}
+
+/// The extensions to enable on `pdevice`, or the first required one it lacks.
+unsafe fn negotiate_extensions(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    version: u32,
+) -> Result<Vec<&'static CStr>, String> {
+    let available = instance
+        .enumerate_device_extension_properties(pdevice)
+        .unwrap_or_default();
+    let mut enabled = Vec::new();
+    for ext in &EXTENSIONS {
+        if ext.core_in.is_some_and(|core| version >= core) {
+            continue;
+        }
+        let supported = version >= ext.min_version
+            && available
+                .iter()
+                .any(|p| p.extension_name_as_c_str() == Ok(ext.name));
+        if supported {
+            enabled.push(ext.name);
+        } else if ext.need == Need::Required {
+            return Err(format!("missing {:?}", ext.name));
+        }
+    }
+    Ok(enabled)
+}
+
+/// What `pdevice` supports of `EXTENSIONS` and `FEATURES`, or the first required one it lacks.
+pub(crate) unsafe fn negotiate(
+    instance: &Instance,
+    api_version: u32,
+    pdevice: vk::PhysicalDevice,
+) -> Result<DeviceCapabilities, String> {
+    let version = api_version.min(instance.get_physical_device_properties(pdevice).api_version);
+    let extensions = negotiate_extensions(instance, pdevice, version)?;
+    let mut caps = DeviceCapabilities {
+        api_version: version,
+        dynamic_rendering_khr: extensions.contains(&dynamic_rendering::NAME),
+        ..Default::default()
+    };
+    // features beyond 1.0 can only be queried from 1.1 on
+    if version >= vk::API_VERSION_1_1 {
+        let mut features12 = vk::PhysicalDeviceVulkan12Features::default();
+        let mut features13 = vk::PhysicalDeviceVulkan13Features::default();
+        let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+        {
+            let mut features = vk::PhysicalDeviceFeatures2::default();
+            if version >= vk::API_VERSION_1_2 {
+                features = features.push_next(&mut features12);
+            }
+            if version >= vk::API_VERSION_1_3 {
+                features = features.push_next(&mut features13);
+            } else if caps.dynamic_rendering_khr {
+                features = features.push_next(&mut dynamic_rendering_features);
+            }
+            instance.get_physical_device_features2(pdevice, &mut features);
+        }
+        caps.timeline_semaphore = features12.timeline_semaphore == vk::TRUE;
+        caps.dynamic_rendering = features13.dynamic_rendering == vk::TRUE
+            || dynamic_rendering_features.dynamic_rendering == vk::TRUE;
+        caps.dynamic_rendering_khr &= caps.dynamic_rendering;
+        caps.synchronization2 = caps.timeline_semaphore && features13.synchronization2 == vk::TRUE;
+    }
+    for (feature, need) in FEATURES {
+        if need == Need::Required && !caps.has(feature) {
+            return Err(format!("missing feature {feature:?}"));
+        }
+    }
+    Ok(caps)
+}
+
+/// Creates the device with exactly the extensions and features in `caps`, and a queue from each
+/// of `families`.
+pub(crate) unsafe fn create_device(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    caps: &DeviceCapabilities,
+    families: &QueueFamilies,
+) -> Device {
+    let extensions = negotiate_extensions(instance, pdevice, caps.api_version)
+        .expect("Device lost a required extension.");
+    let extension_names: Vec<_> = extensions
+        .iter()
+        .filter(|name| **name != dynamic_rendering::NAME || caps.dynamic_rendering_khr)
+        .map(|name| name.as_ptr())
+        .collect();
+    let q_infos: Vec<_> = families
+        .unique()
+        .into_iter()
+        .map(|family| {
+            vk::DeviceQueueCreateInfo::default()
+                .queue_priorities(&[1.0])
+                .queue_family_index(family)
+        })
+        .collect();
+    let mut features12 =
+        vk::PhysicalDeviceVulkan12Features::default().timeline_semaphore(caps.timeline_semaphore);
+    let mut features13 = vk::PhysicalDeviceVulkan13Features::default()
+        .dynamic_rendering(caps.dynamic_rendering)
+        .synchronization2(caps.synchronization2);
+    let mut dynamic_rendering_features =
+        vk::PhysicalDeviceDynamicRenderingFeatures::default().dynamic_rendering(true);
+    let mut device_info = vk::DeviceCreateInfo::default()
+        .enabled_extension_names(&extension_names)
+        .queue_create_infos(&q_infos);
+    if caps.api_version >= vk::API_VERSION_1_2 {
+        device_info = device_info.push_next(&mut features12);
+    }
+    // the 1.3 features struct can't be chained along with the extension's
+    if caps.api_version >= vk::API_VERSION_1_3 {
+        device_info = device_info.push_next(&mut features13);
+    } else if caps.dynamic_rendering_khr {
+        device_info = device_info.push_next(&mut dynamic_rendering_features);
+    }
+    instance
+        .create_device(pdevice, &device_info, None)
+        .expect("Failed to create device.")
+}
@@ -0,0 +1,88 @@
+//! Everything one frame in flight owns: its command buffer, the semaphores and fence it is
+//! synchronized with, and whatever per-frame state subsystems attach, such as the buffers they
+//! rewrite every frame.
+use ash::{vk, Device};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+};
+
+/// Frames in flight unless the config or `WrappedApp::set_frames_in_flight` says otherwise.
+pub(crate) const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+/// Per-frame state of a subsystem, destroyed along with the frame context it is attached to.
+pub(crate) trait FrameData: Any {
+    unsafe fn destroy(&mut self, device: &Device);
+}
+
+pub(crate) struct FrameContext {
+    pub cmd: vk::CommandBuffer,
+    pub image_available: vk::Semaphore,
+    pub render_done: vk::Semaphore,
+    /// Null when frames are tracked with a timeline semaphore.
+    pub fence: vk::Fence,
+    /// The number of the last frame submitted with this context, 0 if there was none yet.
+    pub submitted: u64,
+    data: HashMap<TypeId, Box<dyn FrameData>>,
+}
+
+impl FrameContext {
+    pub unsafe fn new(device: &Device, command_pool: vk::CommandPool, fence: bool) -> Self {
+        let buff_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let semaphore = || {
+            device
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                .expect("Failed to create semaphore.")
+        };
+        FrameContext {
+            cmd: device
+                .allocate_command_buffers(&buff_info)
+                .expect("Failed to allocate command buffers")[0],
+            image_available: semaphore(),
+            render_done: semaphore(),
+            fence: if fence {
+                device
+                    .create_fence(&vk::FenceCreateInfo::default(), None)
+                    .expect("Failed to create fence.")
+            } else {
+                vk::Fence::null()
+            },
+            submitted: 0,
+            data: HashMap::new(),
+        }
+    }
+
+    /// Attaches a subsystem's state, replacing any of the same type.
+    pub fn attach<T: FrameData>(&mut self, data: T) {
+        self.data.insert(TypeId::of::<T>(), Box::new(data));
+    }
+
+    pub fn get<T: FrameData>(&self) -> &T {
+        let data: &dyn Any = &**self
+            .data
+            .get(&TypeId::of::<T>())
+            .expect("Frame data was never attached.");
+        data.downcast_ref().unwrap()
+    }
+
+    pub fn get_mut<T: FrameData>(&mut self) -> &mut T {
+        let data: &mut dyn Any = &mut **self
+            .data
+            .get_mut(&TypeId::of::<T>())
+            .expect("Frame data was never attached.");
+        data.downcast_mut().unwrap()
+    }
+
+    /// Destroys the sync objects and attached data. The command buffer is freed with its pool.
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        for (_, mut data) in self.data.drain() {
+            data.destroy(device);
+        }
+        device.destroy_semaphore(self.image_available, None);
+        device.destroy_semaphore(self.render_done, None);
+        device.destroy_fence(self.fence, None);
+    }
+}
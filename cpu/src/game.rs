@@ -0,0 +1,234 @@
+//! The hooks a game implements to run on top of the renderer. `WrappedApp` owns the game and calls
+//! into it: `init` once the main window exists, `event` for every window event, `update` for every
+//! fixed simulation step and `render` once per frame and window to queue what the frame draws.
+use crate::{
+    app::Shared,
+    capture::Capture,
+    display::DisplayConfig,
+    input::Input,
+    profiler::FrameStats,
+    sprite::SpriteBatch,
+    swapchain::{PresentMode, SurfaceSupport, SwapchainConfig},
+    text::{Fonts, TextBatch},
+    texture::{TextureId, Textures},
+    time::Clock,
+    tonemap::OutputMode,
+    window::AppWindow,
+};
+use ash::vk;
+use glam::{Mat4, UVec2, Vec2};
+use std::collections::HashMap;
+use winit::{
+    error::OsError,
+    event::WindowEvent,
+    event_loop::ActiveEventLoop,
+    window::{Window, WindowAttributes, WindowId},
+};
+
+pub trait Game {
+    /// Called once, after the main window and renderer are created.
+    fn init(&mut self, _ctx: &mut Context) {}
+
+    /// Advances the simulation by `dt` seconds, the clock's fixed step. `ctx.input` reports what
+    /// was pressed and released since the previous step, however many frames that was.
+    fn update(&mut self, _ctx: &mut Context, _dt: f32) {}
+
+    /// Queues what `frame.window` draws. Runs after the frame's updates, right before it is
+    /// recorded.
+    fn render(&mut self, _frame: &mut Frame) {}
+
+    /// Called for every window event, after input and the UI have seen it, except presses and
+    /// other input the UI consumed. `ctx.window` is the window it was sent to.
+    fn event(&mut self, _ctx: &mut Context, _event: &WindowEvent) {}
+}
+
+/// Runs nothing, leaving an empty window with the debug UI.
+impl Game for () {}
+
+/// What a game can use outside of rendering.
+pub struct Context<'a> {
+    /// The window at hand: the one an event was sent to, otherwise the main window.
+    pub window: &'a Window,
+    pub input: &'a mut Input,
+    pub clock: &'a mut Clock,
+    pub fonts: &'a mut Fonts,
+    pub stats: &'a FrameStats,
+    pub(crate) textures: &'a mut Textures,
+    pub(crate) capture: &'a mut Capture,
+    pub(crate) event_loop: &'a ActiveEventLoop,
+    pub(crate) shared: &'a Shared,
+    pub(crate) windows: &'a HashMap<WindowId, AppWindow>,
+    pub(crate) main: WindowId,
+    pub(crate) opened: &'a mut Vec<Window>,
+    pub(crate) closed: &'a mut Vec<WindowId>,
+    pub(crate) displays: &'a mut Vec<(WindowId, DisplayConfig)>,
+    pub(crate) swapchain: &'a mut Option<SwapchainConfig>,
+    pub(crate) requested_output: &'a mut Option<OutputMode>,
+}
+
+impl Context<'_> {
+    /// Opens another window rendering with the same device. It gets its own frames, with
+    /// `Game::render` called for each of them, and receives events from the next one on.
+    pub fn open_window(&mut self, attrs: WindowAttributes) -> Result<WindowId, OsError> {
+        let window = self.event_loop.create_window(attrs)?;
+        let id = window.id();
+        self.opened.push(window);
+        Ok(id)
+    }
+
+    /// Closes window `id` once the current event is handled. Closing the main window quits.
+    pub fn close_window(&mut self, id: WindowId) {
+        self.closed.push(id);
+    }
+
+    /// Switches window `id` between windowed and fullscreen once the current event is handled.
+    pub fn set_display(&mut self, id: WindowId, config: DisplayConfig) {
+        self.displays.push((id, config));
+    }
+
+    /// What window `id`'s surface supports, `None` if there is no such window.
+    pub fn surface_support(&self, id: WindowId) -> Option<SurfaceSupport> {
+        let win = self.windows.get(&id)?;
+        let (capabilities, _, modes) = unsafe { self.shared.swap_support(win.surface) };
+        Some(SurfaceSupport::new(&capabilities, &modes))
+    }
+
+    /// The present mode window `id` uses, after falling back from an unsupported request.
+    pub fn present_mode(&self, id: WindowId) -> Option<PresentMode> {
+        self.windows.get(&id).map(|win| win.present_mode)
+    }
+
+    /// The swapchain setup every window asks for, including a change queued during this event.
+    pub fn swapchain_config(&self) -> SwapchainConfig {
+        self.swapchain.unwrap_or(self.shared.swap_config)
+    }
+
+    /// Changes the present mode and image count of every window once the current event is
+    /// handled, recreating their swapchains.
+    pub fn set_swapchain_config(&mut self, config: SwapchainConfig) {
+        *self.swapchain = Some(config);
+    }
+
+    /// Output modes window `id`'s surface supports, empty if there is no such window.
+    pub fn output_modes(&self, id: WindowId) -> Vec<OutputMode> {
+        let Some(win) = self.windows.get(&id) else {
+            return Vec::new();
+        };
+        let (_, formats, _) = unsafe { self.shared.swap_support(win.surface) };
+        // SDR works on any format, with the shaders doing the sRGB encoding if needed
+        OutputMode::ALL
+            .into_iter()
+            .filter(|mode| *mode == OutputMode::Sdr || mode.pick_format(&formats).is_some())
+            .collect()
+    }
+
+    /// The output mode window `id` uses, which is SDR if the requested one is unsupported.
+    pub fn output_mode(&self, id: WindowId) -> Option<OutputMode> {
+        self.windows.get(&id).map(|win| win.output_mode)
+    }
+
+    /// Switches every window to `mode` once the current event is handled, recreating their
+    /// output passes. Windows whose surface lacks it fall back to SDR.
+    pub fn set_output_mode(&mut self, mode: OutputMode) {
+        *self.requested_output = Some(mode);
+    }
+
+    /// Uploads a `width` by `height` texture for sprites to draw with. `pixels` are RGBA, 8 bits
+    /// per channel in sRGB, row by row from the top left.
+    pub fn create_texture(&mut self, width: u32, height: u32, pixels: &[u8]) -> TextureId {
+        assert_eq!(
+            pixels.len(),
+            width as usize * height as usize * 4,
+            "Texture pixels don't match its size."
+        );
+        unsafe {
+            self.textures.create(
+                self.shared.gpu(),
+                width,
+                height,
+                vk::Format::R8G8B8A8_SRGB,
+                pixels,
+                "game texture",
+            )
+        }
+    }
+
+    /// Overwrites the `size` pixels of texture `id` starting at `offset` with `pixels`, laid out
+    /// like for `create_texture`. Frames already drawn with it are unaffected.
+    pub fn update_texture(&mut self, id: TextureId, offset: UVec2, size: UVec2, pixels: &[u8]) {
+        assert_eq!(
+            pixels.len(),
+            size.x as usize * size.y as usize * 4,
+            "Texture pixels don't match the region's size."
+        );
+        let extent = self.textures.get(id).extent;
+        assert!(
+            offset.x + size.x <= extent.width && offset.y + size.y <= extent.height,
+            "Texture region is out of bounds."
+        );
+        let region = vk::Rect2D {
+            offset: vk::Offset2D {
+                x: offset.x as i32,
+                y: offset.y as i32,
+            },
+            extent: vk::Extent2D {
+                width: size.x,
+                height: size.y,
+            },
+        };
+        unsafe { self.textures.update(self.shared.gpu(), id, region, pixels) };
+    }
+
+    /// Frees texture `id` once the frames drawn with it are done. Sprites must not use it anymore.
+    pub fn free_texture(&mut self, id: TextureId) {
+        assert_ne!(id, TextureId::WHITE, "The white texture can't be freed.");
+        self.textures.retire(id, self.shared.sync.submitted());
+    }
+
+    /// Whether RenderDoc is attached, so frames can be captured.
+    pub fn capture_available(&self) -> bool {
+        self.capture.available()
+    }
+
+    /// Captures the next `frames` frames of the main window with RenderDoc, if it is attached.
+    pub fn capture_frames(&mut self, frames: u32) {
+        self.capture.request(frames);
+    }
+
+    /// The window opened at startup, which paces the simulation.
+    pub fn main_window(&self) -> WindowId {
+        self.main
+    }
+}
+
+/// The current frame's draw queues. Whatever is queued is drawn once and then cleared.
+pub struct Frame<'a> {
+    /// The window being drawn. Each has its own draw queues, UI and camera.
+    pub window: WindowId,
+    pub sprites: &'a mut SpriteBatch,
+    pub texts: &'a mut TextBatch,
+    pub fonts: &'a Fonts,
+    /// UI built here is drawn over the scene in this frame.
+    pub egui: &'a egui::Context,
+    pub input: &'a Input,
+    pub clock: &'a Clock,
+    pub stats: &'a FrameStats,
+    /// The size sprites and text are laid out in, in pixels.
+    pub size: Vec2,
+    /// The camera debug shapes are drawn with. Kept from frame to frame.
+    pub view_proj: Mat4,
+}
+
+impl Frame<'_> {
+    /// Times the sprites queued in `f` on the GPU, which `FrameStats::gpu_ms(name)` then reports
+    /// like the built-in scopes. Scopes can nest. Sprites queued inside are drawn together, after
+    /// the ones queued before the scope and before the ones queued after it, so layers only order
+    /// sprites within the same scope. With debug utils, the scope is also a labeled region that
+    /// RenderDoc captures and validation messages show.
+    pub fn gpu_scope<R>(&mut self, name: &str, f: impl FnOnce(&mut Self) -> R) -> R {
+        self.sprites.begin_scope(name);
+        let result = f(self);
+        self.sprites.end_scope();
+        result
+    }
+}
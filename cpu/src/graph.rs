@@ -0,0 +1,922 @@
+//! A render graph: passes declare which images and buffers they read and write, and the graph
+//! derives the pipeline barriers and layout transitions between them, culls passes whose output
+//! is never used, and places transient images with disjoint lifetimes in the same memory.
+//!
+//! The graph is compiled once per swapchain, since transient images match its extent, and then
+//! executed every frame. Imported resources, such as the swapchain image, can change between
+//! executions.
+use crate::{label::name_object, util::find_memory_type};
+use ash::{vk, Device};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ImageId(usize);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct BufferId(usize);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct PassId(usize);
+
+/// How a pass uses a resource.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Access {
+    /// Cleared, or otherwise fully overwritten, by the pass.
+    ColorAttachment,
+    /// Drawn over, keeping what earlier passes wrote.
+    ColorAttachmentLoad,
+    DepthAttachment,
+    /// Depth tested and written on top of what earlier passes wrote.
+    DepthAttachmentLoad,
+    /// Sampled in a fragment shader.
+    Sampled,
+    /// Read or written by a compute shader.
+    StorageRead,
+    StorageWrite,
+    TransferSrc,
+    TransferDst,
+    VertexBuffer,
+    IndexBuffer,
+    /// Only valid as the final access of an exported image.
+    Present,
+}
+
+impl Access {
+    fn layout(self) -> vk::ImageLayout {
+        match self {
+            Access::ColorAttachment | Access::ColorAttachmentLoad => {
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            }
+            Access::DepthAttachment | Access::DepthAttachmentLoad => {
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            }
+            Access::Sampled => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            Access::StorageRead | Access::StorageWrite => vk::ImageLayout::GENERAL,
+            Access::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            Access::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            Access::Present => vk::ImageLayout::PRESENT_SRC_KHR,
+            Access::VertexBuffer | Access::IndexBuffer => {
+                panic!("{self:?} is a buffer access.")
+            }
+        }
+    }
+
+    fn stage(self) -> vk::PipelineStageFlags {
+        use vk::PipelineStageFlags as ps;
+        match self {
+            Access::ColorAttachment | Access::ColorAttachmentLoad => ps::COLOR_ATTACHMENT_OUTPUT,
+            Access::DepthAttachment | Access::DepthAttachmentLoad => {
+                ps::EARLY_FRAGMENT_TESTS | ps::LATE_FRAGMENT_TESTS
+            }
+            Access::Sampled => ps::FRAGMENT_SHADER,
+            Access::StorageRead | Access::StorageWrite => ps::COMPUTE_SHADER,
+            Access::TransferSrc | Access::TransferDst => ps::TRANSFER,
+            Access::VertexBuffer | Access::IndexBuffer => ps::VERTEX_INPUT,
+            Access::Present => ps::BOTTOM_OF_PIPE,
+        }
+    }
+
+    fn access(self) -> vk::AccessFlags {
+        use vk::AccessFlags as af;
+        match self {
+            Access::ColorAttachment | Access::ColorAttachmentLoad => {
+                af::COLOR_ATTACHMENT_READ | af::COLOR_ATTACHMENT_WRITE
+            }
+            Access::DepthAttachment | Access::DepthAttachmentLoad => {
+                af::DEPTH_STENCIL_ATTACHMENT_READ | af::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            Access::Sampled | Access::StorageRead => af::SHADER_READ,
+            Access::StorageWrite => af::SHADER_WRITE,
+            Access::TransferSrc => af::TRANSFER_READ,
+            Access::TransferDst => af::TRANSFER_WRITE,
+            Access::VertexBuffer => af::VERTEX_ATTRIBUTE_READ,
+            Access::IndexBuffer => af::INDEX_READ,
+            Access::Present => af::empty(),
+        }
+    }
+
+    fn is_write(self) -> bool {
+        matches!(
+            self,
+            Access::ColorAttachment
+                | Access::ColorAttachmentLoad
+                | Access::DepthAttachment
+                | Access::DepthAttachmentLoad
+                | Access::StorageWrite
+                | Access::TransferDst
+        )
+    }
+
+    /// Whether the pass depends on the resource's previous contents, so the passes producing
+    /// them have to be kept.
+    fn is_read(self) -> bool {
+        !matches!(
+            self,
+            Access::ColorAttachment
+                | Access::DepthAttachment
+                | Access::StorageWrite
+                | Access::TransferDst
+        )
+    }
+}
+
+/// Where a resource was last used, which the next use has to wait for.
+#[derive(Clone, Copy)]
+struct State {
+    layout: vk::ImageLayout,
+    stage: vk::PipelineStageFlags,
+    /// Writes that have to be made visible. Reads only need an execution dependency.
+    access: vk::AccessFlags,
+}
+
+/// A transient image, created and owned by the graph.
+#[derive(Clone, Copy)]
+pub(crate) struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub aspect: vk::ImageAspectFlags,
+}
+
+struct GraphImage {
+    name: &'static str,
+    /// `None` for imported images.
+    desc: Option<ImageDesc>,
+    aspect: vk::ImageAspectFlags,
+    image: vk::Image,
+    view: vk::ImageView,
+    /// The state imported images are in when the graph starts.
+    initial: State,
+    /// The access exported images are left in when the graph ends.
+    export: Option<Access>,
+}
+
+struct GraphBuffer {
+    buffer: vk::Buffer,
+    initial: State,
+    export: Option<Access>,
+}
+
+struct Pass {
+    name: &'static str,
+    images: Vec<(ImageId, Access)>,
+    buffers: Vec<(BufferId, Access)>,
+}
+
+/// The legacy flags have the same bits as their synchronization2 counterparts.
+fn stage2(stage: vk::PipelineStageFlags) -> vk::PipelineStageFlags2 {
+    vk::PipelineStageFlags2::from_raw(stage.as_raw() as u64)
+}
+
+fn access2(access: vk::AccessFlags) -> vk::AccessFlags2 {
+    vk::AccessFlags2::from_raw(access.as_raw() as u64)
+}
+
+/// The barriers recorded before a pass, or after the last one for exports.
+#[derive(Default)]
+struct Barriers {
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags,
+    /// The image handles are filled in when recording, since imported images can change.
+    images: Vec<(ImageId, vk::ImageMemoryBarrier<'static>)>,
+    buffers: Vec<vk::BufferMemoryBarrier<'static>>,
+}
+
+impl Barriers {
+    unsafe fn record(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        images: &[GraphImage],
+        sync2: bool,
+    ) {
+        if self.images.is_empty() && self.buffers.is_empty() {
+            return;
+        }
+        if sync2 {
+            self.record2(device, cmd, images);
+            return;
+        }
+        let image_barriers: Vec<_> = self
+            .images
+            .iter()
+            .map(|(id, barrier)| barrier.image(images[id.0].image))
+            .collect();
+        device.cmd_pipeline_barrier(
+            cmd,
+            self.src_stage,
+            self.dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &self.buffers,
+            &image_barriers,
+        );
+    }
+
+    unsafe fn record2(&self, device: &Device, cmd: vk::CommandBuffer, images: &[GraphImage]) {
+        let (src_stage, dst_stage) = (stage2(self.src_stage), stage2(self.dst_stage));
+        let image_barriers: Vec<_> = self
+            .images
+            .iter()
+            .map(|(id, b)| {
+                vk::ImageMemoryBarrier2::default()
+                    .src_stage_mask(src_stage)
+                    .dst_stage_mask(dst_stage)
+                    .src_access_mask(access2(b.src_access_mask))
+                    .dst_access_mask(access2(b.dst_access_mask))
+                    .old_layout(b.old_layout)
+                    .new_layout(b.new_layout)
+                    .src_queue_family_index(b.src_queue_family_index)
+                    .dst_queue_family_index(b.dst_queue_family_index)
+                    .image(images[id.0].image)
+                    .subresource_range(b.subresource_range)
+            })
+            .collect();
+        let buffer_barriers: Vec<_> = self
+            .buffers
+            .iter()
+            .map(|b| {
+                vk::BufferMemoryBarrier2::default()
+                    .src_stage_mask(src_stage)
+                    .dst_stage_mask(dst_stage)
+                    .src_access_mask(access2(b.src_access_mask))
+                    .dst_access_mask(access2(b.dst_access_mask))
+                    .src_queue_family_index(b.src_queue_family_index)
+                    .dst_queue_family_index(b.dst_queue_family_index)
+                    .buffer(b.buffer)
+                    .offset(b.offset)
+                    .size(b.size)
+            })
+            .collect();
+        let dependency_info = vk::DependencyInfo::default()
+            .image_memory_barriers(&image_barriers)
+            .buffer_memory_barriers(&buffer_barriers);
+        device.cmd_pipeline_barrier2(cmd, &dependency_info);
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct RenderGraph {
+    images: Vec<GraphImage>,
+    buffers: Vec<GraphBuffer>,
+    passes: Vec<Pass>,
+    /// The passes that survived culling, in submission order, with the barriers before each.
+    schedule: Vec<(PassId, Barriers)>,
+    final_barriers: Barriers,
+    /// One allocation per group of aliased transient images.
+    memory: Vec<vk::DeviceMemory>,
+    /// Records barriers with `cmd_pipeline_barrier2`.
+    sync2: bool,
+}
+
+/// Declares the resources a pass uses. Returned by `RenderGraph::add_pass`.
+pub(crate) struct PassBuilder<'a> {
+    graph: &'a mut RenderGraph,
+    pass: Pass,
+}
+
+impl PassBuilder<'_> {
+    pub fn image(mut self, image: ImageId, access: Access) -> Self {
+        self.pass.images.push((image, access));
+        self
+    }
+
+    /// No built-in pass reads buffers written on the GPU at the moment.
+    #[allow(dead_code)]
+    pub fn buffer(mut self, buffer: BufferId, access: Access) -> Self {
+        self.pass.buffers.push((buffer, access));
+        self
+    }
+
+    pub fn build(self) -> PassId {
+        self.graph.passes.push(self.pass);
+        PassId(self.graph.passes.len() - 1)
+    }
+}
+
+impl RenderGraph {
+    pub fn new(sync2: bool) -> Self {
+        RenderGraph {
+            sync2,
+            ..Default::default()
+        }
+    }
+
+    /// Declares an image the graph creates, and may alias with others, when compiled.
+    pub fn create_image(&mut self, name: &'static str, desc: ImageDesc) -> ImageId {
+        self.images.push(GraphImage {
+            name,
+            desc: Some(desc),
+            aspect: desc.aspect,
+            image: vk::Image::null(),
+            view: vk::ImageView::null(),
+            initial: State {
+                layout: vk::ImageLayout::UNDEFINED,
+                stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                access: vk::AccessFlags::empty(),
+            },
+            export: None,
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    /// Declares an image owned elsewhere, which is in `layout` and done being used by `stage` when
+    /// the graph starts. Its handles are set with `set_image` before executing.
+    pub fn import_image(
+        &mut self,
+        name: &'static str,
+        aspect: vk::ImageAspectFlags,
+        layout: vk::ImageLayout,
+        stage: vk::PipelineStageFlags,
+    ) -> ImageId {
+        self.images.push(GraphImage {
+            name,
+            desc: None,
+            aspect,
+            image: vk::Image::null(),
+            view: vk::ImageView::null(),
+            initial: State {
+                layout,
+                stage,
+                access: vk::AccessFlags::empty(),
+            },
+            export: None,
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    #[allow(dead_code)]
+    pub fn import_buffer(&mut self, buffer: vk::Buffer, stage: vk::PipelineStageFlags) -> BufferId {
+        self.buffers.push(GraphBuffer {
+            buffer,
+            initial: State {
+                layout: vk::ImageLayout::UNDEFINED,
+                stage,
+                access: vk::AccessFlags::empty(),
+            },
+            export: None,
+        });
+        BufferId(self.buffers.len() - 1)
+    }
+
+    /// Marks an image as a result of the graph, left in `access` once it has run. Passes that
+    /// contribute to no exported resource are culled.
+    pub fn export_image(&mut self, image: ImageId, access: Access) {
+        self.images[image.0].export = Some(access);
+    }
+
+    #[allow(dead_code)]
+    pub fn export_buffer(&mut self, buffer: BufferId, access: Access) {
+        self.buffers[buffer.0].export = Some(access);
+    }
+
+    pub fn add_pass(&mut self, name: &'static str) -> PassBuilder<'_> {
+        PassBuilder {
+            graph: self,
+            pass: Pass {
+                name,
+                images: Vec::new(),
+                buffers: Vec::new(),
+            },
+        }
+    }
+
+    pub fn set_image(&mut self, id: ImageId, image: vk::Image, view: vk::ImageView) {
+        let graph_image = &mut self.images[id.0];
+        assert!(
+            graph_image.desc.is_none(),
+            "Only imported images can be set."
+        );
+        graph_image.image = image;
+        graph_image.view = view;
+    }
+
+    pub fn view(&self, id: ImageId) -> vk::ImageView {
+        self.images[id.0].view
+    }
+
+    /// The surviving passes in the order they have to be recorded.
+    pub fn schedule(&self) -> Vec<PassId> {
+        self.schedule.iter().map(|(pass, _)| *pass).collect()
+    }
+
+    pub fn pass_name(&self, pass: PassId) -> &'static str {
+        self.passes[pass.0].name
+    }
+
+    /// Culls unused passes, derives every barrier and creates the transient images.
+    pub unsafe fn compile(
+        &mut self,
+        device: &Device,
+        mem_props: &vk::PhysicalDeviceMemoryProperties,
+    ) {
+        let kept = self.cull();
+        self.allocate_transients(device, mem_props, &kept);
+        self.derive_barriers(kept);
+    }
+
+    /// Fills in the schedule with the `kept` passes and the barriers before each, and the final
+    /// barriers of the exports.
+    fn derive_barriers(&mut self, kept: Vec<PassId>) {
+        let mut image_states: Vec<State> = self.images.iter().map(|i| i.initial).collect();
+        let mut buffer_states: Vec<State> = self.buffers.iter().map(|b| b.initial).collect();
+        let mut first_use = vec![true; self.images.len()];
+        self.schedule.clear();
+        for pass in kept {
+            let mut barriers = Barriers::default();
+            for &(image, access) in &self.passes[pass.0].images {
+                let mut from = image_states[image.0];
+                // a transient's memory may have just been used by an image aliasing it, or by
+                // the previous frame, so its first use waits for everything before it
+                if std::mem::take(&mut first_use[image.0]) && self.images[image.0].desc.is_some() {
+                    from = State {
+                        layout: vk::ImageLayout::UNDEFINED,
+                        stage: vk::PipelineStageFlags::ALL_COMMANDS,
+                        access: vk::AccessFlags::MEMORY_WRITE,
+                    };
+                }
+                self.image_barrier(&mut barriers, image, from, access);
+                image_states[image.0] = state_after(access);
+            }
+            for &(buffer, access) in &self.passes[pass.0].buffers {
+                let from = buffer_states[buffer.0];
+                if from.access.is_empty() && !access.is_write() {
+                    // read after read, or after nothing, needs no barrier
+                    continue;
+                }
+                barriers.src_stage |= from.stage;
+                barriers.dst_stage |= access.stage();
+                barriers.buffers.push(
+                    vk::BufferMemoryBarrier::default()
+                        .src_access_mask(from.access)
+                        .dst_access_mask(access.access())
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .buffer(self.buffers[buffer.0].buffer)
+                        .size(vk::WHOLE_SIZE),
+                );
+                buffer_states[buffer.0] = state_after(access);
+            }
+            self.schedule.push((pass, barriers));
+        }
+
+        let mut final_barriers = Barriers::default();
+        for (i, image) in self.images.iter().enumerate() {
+            if let Some(access) = image.export {
+                self.image_barrier(&mut final_barriers, ImageId(i), image_states[i], access);
+            }
+        }
+        for (i, buffer) in self.buffers.iter().enumerate() {
+            if let Some(access) = buffer.export {
+                let from = buffer_states[i];
+                final_barriers.src_stage |= from.stage;
+                final_barriers.dst_stage |= access.stage();
+                final_barriers.buffers.push(
+                    vk::BufferMemoryBarrier::default()
+                        .src_access_mask(from.access)
+                        .dst_access_mask(access.access())
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .buffer(buffer.buffer)
+                        .size(vk::WHOLE_SIZE),
+                );
+            }
+        }
+        self.final_barriers = final_barriers;
+    }
+
+    /// Walks the passes backwards from the exported resources, keeping the passes that write
+    /// something a kept pass, or an export, needs.
+    fn cull(&self) -> Vec<PassId> {
+        let mut images_needed: Vec<bool> = self.images.iter().map(|i| i.export.is_some()).collect();
+        let mut buffers_needed: Vec<bool> =
+            self.buffers.iter().map(|b| b.export.is_some()).collect();
+        let mut kept = Vec::new();
+        for (i, pass) in self.passes.iter().enumerate().rev() {
+            let needed = pass
+                .images
+                .iter()
+                .any(|(img, access)| access.is_write() && images_needed[img.0])
+                || pass
+                    .buffers
+                    .iter()
+                    .any(|(buf, access)| access.is_write() && buffers_needed[buf.0]);
+            if !needed {
+                continue;
+            }
+            kept.push(PassId(i));
+            // anything this pass writes is produced here, and what it reads, including what it
+            // draws over, has to come from earlier
+            for (img, access) in &pass.images {
+                if access.is_write() {
+                    images_needed[img.0] = false;
+                }
+            }
+            for (buf, access) in &pass.buffers {
+                if access.is_write() {
+                    buffers_needed[buf.0] = false;
+                }
+            }
+            for (img, access) in &pass.images {
+                images_needed[img.0] |= access.is_read();
+            }
+            for (buf, access) in &pass.buffers {
+                buffers_needed[buf.0] |= access.is_read();
+            }
+        }
+        kept.reverse();
+        kept
+    }
+
+    fn image_barrier(&self, barriers: &mut Barriers, image: ImageId, from: State, to: Access) {
+        // read after read in the same layout needs no barrier
+        if from.layout == to.layout() && from.access.is_empty() && !to.is_write() {
+            return;
+        }
+        barriers.src_stage |= from.stage;
+        barriers.dst_stage |= to.stage();
+        barriers.images.push((
+            image,
+            vk::ImageMemoryBarrier::default()
+                .old_layout(from.layout)
+                .new_layout(to.layout())
+                .src_access_mask(from.access)
+                .dst_access_mask(to.access())
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(
+                    vk::ImageSubresourceRange::default()
+                        .aspect_mask(self.images[image.0].aspect)
+                        .level_count(1)
+                        .layer_count(1),
+                ),
+        ));
+    }
+
+    /// Creates the transient images used by `kept` passes and binds them to shared memory. Images
+    /// whose lifetimes, in passes, don't overlap share an allocation.
+    unsafe fn allocate_transients(
+        &mut self,
+        device: &Device,
+        mem_props: &vk::PhysicalDeviceMemoryProperties,
+        kept: &[PassId],
+    ) {
+        // first and last position in the schedule of every used transient
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.images.len()];
+        for (pos, pass) in kept.iter().enumerate() {
+            for (img, _) in &self.passes[pass.0].images {
+                let lifetime = &mut lifetimes[img.0];
+                *lifetime = Some(lifetime.map_or((pos, pos), |(first, _)| (first, pos)));
+            }
+        }
+        let mut usages = vec![vk::ImageUsageFlags::empty(); self.images.len()];
+        for pass in kept {
+            for (img, access) in &self.passes[pass.0].images {
+                usages[img.0] |= match access {
+                    Access::ColorAttachment | Access::ColorAttachmentLoad => {
+                        vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    }
+                    Access::DepthAttachment | Access::DepthAttachmentLoad => {
+                        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                    }
+                    Access::Sampled => vk::ImageUsageFlags::SAMPLED,
+                    Access::StorageRead | Access::StorageWrite => vk::ImageUsageFlags::STORAGE,
+                    Access::TransferSrc => vk::ImageUsageFlags::TRANSFER_SRC,
+                    Access::TransferDst => vk::ImageUsageFlags::TRANSFER_DST,
+                    _ => vk::ImageUsageFlags::empty(),
+                };
+            }
+        }
+
+        let mut transients: Vec<usize> = (0..self.images.len())
+            .filter(|i| self.images[*i].desc.is_some() && lifetimes[*i].is_some())
+            .collect();
+        transients.sort_by_key(|i| lifetimes[*i].unwrap().0);
+        // each slot is one allocation: the last position it is used at, its requirements, and
+        // the images placed in it
+        let mut slots: Vec<(usize, vk::MemoryRequirements, Vec<usize>)> = Vec::new();
+        for i in transients {
+            let desc = self.images[i].desc.unwrap();
+            let image_info = vk::ImageCreateInfo::default()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(desc.format)
+                .extent(vk::Extent3D {
+                    width: desc.extent.width,
+                    height: desc.extent.height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(usages[i])
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED);
+            let image = device
+                .create_image(&image_info, None)
+                .expect("Failed to create transient image.");
+            name_object(image, self.images[i].name);
+            self.images[i].image = image;
+            let reqs = device.get_image_memory_requirements(image);
+            let (first, last) = lifetimes[i].unwrap();
+            let slot = slots.iter_mut().find(|(slot_last, slot_reqs, _)| {
+                *slot_last < first && slot_reqs.memory_type_bits & reqs.memory_type_bits != 0
+            });
+            match slot {
+                Some((slot_last, slot_reqs, images)) => {
+                    *slot_last = last;
+                    slot_reqs.size = slot_reqs.size.max(reqs.size);
+                    slot_reqs.alignment = slot_reqs.alignment.max(reqs.alignment);
+                    slot_reqs.memory_type_bits &= reqs.memory_type_bits;
+                    images.push(i);
+                }
+                None => slots.push((last, reqs, vec![i])),
+            }
+        }
+
+        for (_, reqs, images) in slots {
+            let alloc_info = vk::MemoryAllocateInfo::default()
+                .allocation_size(reqs.size)
+                .memory_type_index(find_memory_type(
+                    mem_props,
+                    reqs.memory_type_bits,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ));
+            let memory = device
+                .allocate_memory(&alloc_info, None)
+                .expect("Failed to allocate transient image memory.");
+            for i in images {
+                let image = &mut self.images[i];
+                device
+                    .bind_image_memory(image.image, memory, 0)
+                    .expect("Failed to bind transient image memory.");
+                let view_info = vk::ImageViewCreateInfo::default()
+                    .image(image.image)
+                    .format(image.desc.unwrap().format)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::default()
+                            .aspect_mask(image.aspect)
+                            .level_count(1)
+                            .layer_count(1),
+                    );
+                image.view = device
+                    .create_image_view(&view_info, None)
+                    .unwrap_or_else(|_| panic!("Failed to create view of {}.", image.name));
+                name_object(image.view, image.name);
+            }
+            self.memory.push(memory);
+        }
+    }
+
+    /// Records the barriers needed before `pass`. Call right before recording the pass itself.
+    pub unsafe fn begin_pass(&self, device: &Device, cmd: vk::CommandBuffer, pass: PassId) {
+        let (_, barriers) = self
+            .schedule
+            .iter()
+            .find(|(p, _)| *p == pass)
+            .expect("Pass was culled.");
+        barriers.record(device, cmd, &self.images, self.sync2);
+    }
+
+    /// Records the transitions of exported resources. Call after the last pass.
+    pub unsafe fn finish(&self, device: &Device, cmd: vk::CommandBuffer) {
+        self.final_barriers
+            .record(device, cmd, &self.images, self.sync2);
+    }
+
+    /// Destroys the transient images and their memory. Imported resources are left alone.
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        for image in self.images.iter().filter(|i| i.desc.is_some()) {
+            device.destroy_image_view(image.view, None);
+            device.destroy_image(image.image, None);
+        }
+        for memory in self.memory.drain(..) {
+            device.free_memory(memory, None);
+        }
+        *self = RenderGraph::new(self.sync2);
+    }
+}
+
+fn state_after(access: Access) -> State {
+    State {
+        layout: if matches!(access, Access::VertexBuffer | Access::IndexBuffer) {
+            vk::ImageLayout::UNDEFINED
+        } else {
+            access.layout()
+        },
+        stage: access.stage(),
+        access: if access.is_write() {
+            access.access()
+        } else {
+            vk::AccessFlags::empty()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transient(graph: &mut RenderGraph, name: &'static str) -> ImageId {
+        graph.create_image(
+            name,
+            ImageDesc {
+                format: vk::Format::R16G16B16A16_SFLOAT,
+                extent: vk::Extent2D {
+                    width: 64,
+                    height: 64,
+                },
+                aspect: vk::ImageAspectFlags::COLOR,
+            },
+        )
+    }
+
+    fn swapchain(graph: &mut RenderGraph) -> ImageId {
+        let image = graph.import_image(
+            "swapchain",
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        );
+        graph.export_image(image, Access::Present);
+        image
+    }
+
+    fn barriers(graph: &RenderGraph, pass: PassId) -> &Barriers {
+        &graph.schedule.iter().find(|(p, _)| *p == pass).unwrap().1
+    }
+
+    #[test]
+    fn culls_passes_without_exported_output() {
+        let mut graph = RenderGraph::new(false);
+        let unused = transient(&mut graph, "unused");
+        let swapchain = swapchain(&mut graph);
+        graph
+            .add_pass("unused")
+            .image(unused, Access::ColorAttachment)
+            .build();
+        let output = graph
+            .add_pass("output")
+            .image(swapchain, Access::ColorAttachment)
+            .build();
+        assert_eq!(graph.cull(), vec![output]);
+    }
+
+    #[test]
+    fn keeps_producers_of_what_is_read() {
+        let mut graph = RenderGraph::new(false);
+        let hdr = transient(&mut graph, "hdr");
+        let swapchain = swapchain(&mut graph);
+        let scene = graph
+            .add_pass("scene")
+            .image(hdr, Access::ColorAttachment)
+            .build();
+        let output = graph
+            .add_pass("output")
+            .image(hdr, Access::Sampled)
+            .image(swapchain, Access::ColorAttachment)
+            .build();
+        assert_eq!(graph.cull(), vec![scene, output]);
+    }
+
+    #[test]
+    fn keeps_producers_of_what_is_drawn_over() {
+        let mut graph = RenderGraph::new(false);
+        let hdr = transient(&mut graph, "hdr");
+        let swapchain = swapchain(&mut graph);
+        let scene = graph
+            .add_pass("scene")
+            .image(hdr, Access::ColorAttachment)
+            .build();
+        let overlay = graph
+            .add_pass("overlay")
+            .image(hdr, Access::ColorAttachmentLoad)
+            .build();
+        let output = graph
+            .add_pass("output")
+            .image(hdr, Access::Sampled)
+            .image(swapchain, Access::ColorAttachment)
+            .build();
+        assert_eq!(graph.cull(), vec![scene, overlay, output]);
+    }
+
+    #[test]
+    fn culls_producers_of_what_is_overwritten() {
+        let mut graph = RenderGraph::new(false);
+        let hdr = transient(&mut graph, "hdr");
+        let swapchain = swapchain(&mut graph);
+        graph
+            .add_pass("scene")
+            .image(hdr, Access::ColorAttachment)
+            .build();
+        let clear = graph
+            .add_pass("clear")
+            .image(hdr, Access::ColorAttachment)
+            .build();
+        let output = graph
+            .add_pass("output")
+            .image(hdr, Access::Sampled)
+            .image(swapchain, Access::ColorAttachment)
+            .build();
+        assert_eq!(graph.cull(), vec![clear, output]);
+    }
+
+    #[test]
+    fn derives_transitions_between_passes() {
+        let mut graph = RenderGraph::new(false);
+        let hdr = transient(&mut graph, "hdr");
+        let swapchain = swapchain(&mut graph);
+        let scene = graph
+            .add_pass("scene")
+            .image(hdr, Access::ColorAttachment)
+            .build();
+        let output = graph
+            .add_pass("output")
+            .image(hdr, Access::Sampled)
+            .image(swapchain, Access::ColorAttachment)
+            .build();
+        let kept = graph.cull();
+        graph.derive_barriers(kept);
+        assert_eq!(graph.schedule(), vec![scene, output]);
+
+        // a transient's first use waits for anything that used its memory before
+        let first = barriers(&graph, scene);
+        assert_eq!(first.images.len(), 1);
+        let (image, barrier) = first.images[0];
+        assert_eq!(image, hdr);
+        assert_eq!(barrier.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(
+            barrier.new_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(first.src_stage, vk::PipelineStageFlags::ALL_COMMANDS);
+
+        let second = barriers(&graph, output);
+        let (_, hdr_barrier) = second.images.iter().find(|(i, _)| *i == hdr).unwrap();
+        assert_eq!(
+            hdr_barrier.old_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(
+            hdr_barrier.new_layout,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+        assert_eq!(
+            hdr_barrier.src_access_mask,
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        );
+        assert_eq!(hdr_barrier.dst_access_mask, vk::AccessFlags::SHADER_READ);
+        assert!(second
+            .src_stage
+            .contains(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT));
+        assert!(second
+            .dst_stage
+            .contains(vk::PipelineStageFlags::FRAGMENT_SHADER));
+        assert!(second.images.iter().any(|(i, _)| *i == swapchain));
+
+        let (image, barrier) = graph.final_barriers.images[0];
+        assert_eq!(image, swapchain);
+        assert_eq!(
+            barrier.old_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(barrier.new_layout, vk::ImageLayout::PRESENT_SRC_KHR);
+    }
+
+    #[test]
+    fn reads_in_the_same_layout_need_no_barrier() {
+        let mut graph = RenderGraph::new(false);
+        let texture = graph.import_image(
+            "texture",
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        );
+        let swapchain = swapchain(&mut graph);
+        let first = graph
+            .add_pass("first")
+            .image(texture, Access::Sampled)
+            .image(swapchain, Access::ColorAttachment)
+            .build();
+        let second = graph
+            .add_pass("second")
+            .image(texture, Access::Sampled)
+            .image(swapchain, Access::ColorAttachmentLoad)
+            .build();
+        let kept = graph.cull();
+        graph.derive_barriers(kept);
+        assert_eq!(graph.schedule(), vec![first, second]);
+        for pass in [first, second] {
+            assert!(barriers(&graph, pass)
+                .images
+                .iter()
+                .all(|(i, _)| *i != texture));
+        }
+        // drawing over an attachment still waits for the previous pass's writes
+        let (_, barrier) = barriers(&graph, second).images[0];
+        assert_eq!(
+            barrier.src_access_mask,
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        );
+    }
+}
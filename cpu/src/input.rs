@@ -0,0 +1,503 @@
+//! Keyboard, mouse and gamepad state, collected from window events and queried by game code.
+//! "Pressed" and "released" are only true for the frame in which the change happened, "held" for
+//! as long as the button is down. Inside `Game::update` they are instead true for the first fixed
+//! step after the change, so a step sees every press even when frames without a step come in
+//! between, and a frame running several steps doesn't report it to each of them. The cursor delta
+//! and scroll are split between steps the same way.
+use glam::{vec2, Vec2};
+use std::{collections::HashMap, collections::HashSet, hash::Hash};
+pub use winit::{event::MouseButton, keyboard::KeyCode, keyboard::ModifiersState};
+use winit::{
+    event::{ElementState, MouseScrollDelta, WindowEvent},
+    window::WindowId,
+};
+
+/// Touchpads scroll in pixels, mouse wheels in lines. Pixel deltas are converted at this rate.
+const PIXELS_PER_LINE: f32 = 20.0;
+
+/// winit does not read gamepads, so their buttons are fed in through `Input::set_gamepad_button`
+/// by whatever gamepad library the game uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    /// The bottom face button (A on Xbox, Cross on PlayStation).
+    South,
+    East,
+    West,
+    North,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// Something that can trigger an action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+/// Named actions and the bindings that trigger them, e.g. "jump" on Space or the gamepad's south
+/// button. An action is held while any of its bindings is held.
+#[derive(Default)]
+pub struct ActionMap {
+    bindings: HashMap<String, Vec<Binding>>,
+}
+
+impl ActionMap {
+    /// Adds `binding` to `action`, keeping any existing bindings.
+    pub fn bind(&mut self, action: &str, binding: Binding) -> &mut Self {
+        let bindings = self.bindings.entry(action.to_owned()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+        self
+    }
+
+    pub fn unbind(&mut self, action: &str, binding: Binding) {
+        if let Some(bindings) = self.bindings.get_mut(action) {
+            bindings.retain(|b| *b != binding);
+        }
+    }
+
+    /// Removes every binding of `action`.
+    pub fn clear(&mut self, action: &str) {
+        self.bindings.remove(action);
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.bindings.get(action).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Per frame button state for one kind of button.
+struct Buttons<T> {
+    held: HashSet<T>,
+    pressed: HashSet<T>,
+    released: HashSet<T>,
+    /// Changes since the last fixed step.
+    step_pressed: HashSet<T>,
+    step_released: HashSet<T>,
+}
+
+impl<T> Default for Buttons<T> {
+    fn default() -> Self {
+        Buttons {
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            released: HashSet::new(),
+            step_pressed: HashSet::new(),
+            step_released: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Copy + Eq + Hash> Buttons<T> {
+    fn set(&mut self, button: T, down: bool) {
+        if down {
+            // ignore key repeats
+            if self.held.insert(button) {
+                self.pressed.insert(button);
+                self.step_pressed.insert(button);
+            }
+        } else if self.held.remove(&button) {
+            self.released.insert(button);
+            self.step_released.insert(button);
+        }
+    }
+
+    fn release_all(&mut self) {
+        self.step_released.extend(self.held.iter().copied());
+        self.released.extend(self.held.drain());
+    }
+
+    fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+    }
+
+    fn end_step(&mut self) {
+        self.step_pressed.clear();
+        self.step_released.clear();
+    }
+
+    fn pressed(&self, step: bool) -> &HashSet<T> {
+        if step {
+            &self.step_pressed
+        } else {
+            &self.pressed
+        }
+    }
+
+    fn released(&self, step: bool) -> &HashSet<T> {
+        if step {
+            &self.step_released
+        } else {
+            &self.released
+        }
+    }
+}
+
+/// Whether input has to see `event` even when the UI consumed it: releases, so nothing stays
+/// held, and cursor movement, so the next delta isn't measured from a stale position.
+pub(crate) fn must_track(event: &WindowEvent) -> bool {
+    match event {
+        WindowEvent::KeyboardInput { event, .. } => event.state == ElementState::Released,
+        WindowEvent::MouseInput { state, .. } => *state == ElementState::Released,
+        WindowEvent::CursorMoved { .. }
+        | WindowEvent::CursorEntered { .. }
+        | WindowEvent::CursorLeft { .. } => true,
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+pub struct Input {
+    keys: Buttons<KeyCode>,
+    mouse: Buttons<MouseButton>,
+    gamepad: Buttons<GamepadButton>,
+    cursor: Vec2,
+    /// The window `cursor` is in.
+    cursor_window: Option<WindowId>,
+    /// The last position in each window the cursor is in, which deltas are measured from.
+    last_cursor: HashMap<WindowId, Vec2>,
+    cursor_delta: Vec2,
+    scroll: Vec2,
+    step_cursor_delta: Vec2,
+    step_scroll: Vec2,
+    /// Set while a fixed step runs, which then sees the changes since the last step.
+    in_step: bool,
+    modifiers: ModifiersState,
+    pub actions: ActionMap,
+}
+
+impl Input {
+    pub(crate) fn handle_event(&mut self, window: WindowId, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                if let winit::keyboard::PhysicalKey::Code(code) = event.physical_key {
+                    self.keys.set(code, event.state == ElementState::Pressed);
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.mouse.set(*button, *state == ElementState::Pressed)
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let pos = vec2(position.x as f32, position.y as f32);
+                // the first position in a window has nothing to move from, and positions in
+                // different windows aren't comparable
+                if let Some(last) = self.last_cursor.insert(window, pos) {
+                    self.cursor_delta += pos - last;
+                    self.step_cursor_delta += pos - last;
+                }
+                self.cursor = pos;
+                self.cursor_window = Some(window);
+            }
+            WindowEvent::CursorEntered { .. } | WindowEvent::CursorLeft { .. } => {
+                self.last_cursor.remove(&window);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => vec2(*x, *y),
+                    MouseScrollDelta::PixelDelta(d) => {
+                        vec2(d.x as f32, d.y as f32) / PIXELS_PER_LINE
+                    }
+                };
+                self.scroll += lines;
+                self.step_scroll += lines;
+            }
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+            // releases that happen while unfocused never reach us
+            WindowEvent::Focused(false) => {
+                self.keys.release_all();
+                self.mouse.release_all();
+                self.modifiers = ModifiersState::empty();
+            }
+            _ => (),
+        }
+    }
+
+    /// Clears everything that only lasts a single frame. Called once the frame is rendered.
+    pub(crate) fn end_frame(&mut self) {
+        self.keys.end_frame();
+        self.mouse.end_frame();
+        self.gamepad.end_frame();
+        self.cursor_delta = Vec2::ZERO;
+        self.scroll = Vec2::ZERO;
+    }
+
+    /// Switches queries over to the changes since the last fixed step. Called before each step.
+    pub(crate) fn begin_step(&mut self) {
+        self.in_step = true;
+    }
+
+    /// Clears the changes the step has seen and switches queries back to the frame's. Called
+    /// after each step.
+    pub(crate) fn end_step(&mut self) {
+        self.keys.end_step();
+        self.mouse.end_step();
+        self.gamepad.end_step();
+        self.step_cursor_delta = Vec2::ZERO;
+        self.step_scroll = Vec2::ZERO;
+        self.in_step = false;
+    }
+
+    /// Feeds a gamepad button change from an external gamepad library.
+    pub fn set_gamepad_button(&mut self, button: GamepadButton, down: bool) {
+        self.gamepad.set(button, down);
+    }
+
+    pub fn key_pressed(&self, key: KeyCode) -> bool {
+        self.keys.pressed(self.in_step).contains(&key)
+    }
+
+    pub fn key_held(&self, key: KeyCode) -> bool {
+        self.keys.held.contains(&key)
+    }
+
+    pub fn key_released(&self, key: KeyCode) -> bool {
+        self.keys.released(self.in_step).contains(&key)
+    }
+
+    pub fn mouse_pressed(&self, button: MouseButton) -> bool {
+        self.mouse.pressed(self.in_step).contains(&button)
+    }
+
+    pub fn mouse_held(&self, button: MouseButton) -> bool {
+        self.mouse.held.contains(&button)
+    }
+
+    pub fn mouse_released(&self, button: MouseButton) -> bool {
+        self.mouse.released(self.in_step).contains(&button)
+    }
+
+    pub fn gamepad_pressed(&self, button: GamepadButton) -> bool {
+        self.gamepad.pressed(self.in_step).contains(&button)
+    }
+
+    pub fn gamepad_held(&self, button: GamepadButton) -> bool {
+        self.gamepad.held.contains(&button)
+    }
+
+    pub fn gamepad_released(&self, button: GamepadButton) -> bool {
+        self.gamepad.released(self.in_step).contains(&button)
+    }
+
+    /// Cursor position in physical pixels from the top left of `cursor_window`.
+    pub fn cursor_pos(&self) -> Vec2 {
+        self.cursor
+    }
+
+    /// The window the cursor last moved in, `None` before it moved at all.
+    pub fn cursor_window(&self) -> Option<WindowId> {
+        self.cursor_window
+    }
+
+    /// How far the cursor moved this frame, or since the last step, in physical pixels.
+    pub fn cursor_delta(&self) -> Vec2 {
+        if self.in_step {
+            self.step_cursor_delta
+        } else {
+            self.cursor_delta
+        }
+    }
+
+    /// Scrolling this frame, or since the last step, in lines. Positive y scrolls up.
+    pub fn scroll(&self) -> Vec2 {
+        if self.in_step {
+            self.step_scroll
+        } else {
+            self.scroll
+        }
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    /// Pressed, held and released state of a single binding.
+    fn binding_state(&self, binding: Binding) -> [bool; 3] {
+        fn state<T: Copy + Eq + Hash>(buttons: &Buttons<T>, b: T, step: bool) -> [bool; 3] {
+            [
+                buttons.pressed(step).contains(&b),
+                buttons.held.contains(&b),
+                buttons.released(step).contains(&b),
+            ]
+        }
+        match binding {
+            Binding::Key(k) => state(&self.keys, k, self.in_step),
+            Binding::Mouse(m) => state(&self.mouse, m, self.in_step),
+            Binding::Gamepad(g) => state(&self.gamepad, g, self.in_step),
+        }
+    }
+
+    /// True when the first binding of `action` was pressed this frame, while none were held.
+    pub fn action_pressed(&self, action: &str) -> bool {
+        let states = self
+            .actions
+            .bindings(action)
+            .iter()
+            .map(|b| self.binding_state(*b));
+        let mut pressed = false;
+        for [p, held, _] in states {
+            if held && !p {
+                return false;
+            }
+            pressed |= p;
+        }
+        pressed
+    }
+
+    pub fn action_held(&self, action: &str) -> bool {
+        let bindings = self.actions.bindings(action);
+        bindings.iter().any(|b| self.binding_state(*b)[1])
+    }
+
+    /// True when the last held binding of `action` was released this frame.
+    pub fn action_released(&self, action: &str) -> bool {
+        let bindings = self.actions.bindings(action);
+        bindings.iter().any(|b| self.binding_state(*b)[2]) && !self.action_held(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::DeviceId;
+
+    const JUMP: &str = "jump";
+
+    /// Jump bound to two gamepad buttons.
+    fn input() -> Input {
+        let mut input = Input::default();
+        input
+            .actions
+            .bind(JUMP, Binding::Gamepad(GamepadButton::South))
+            .bind(JUMP, Binding::Gamepad(GamepadButton::East));
+        input
+    }
+
+    #[test]
+    fn repeats_are_not_presses() {
+        let mut buttons = Buttons::default();
+        buttons.set(GamepadButton::South, true);
+        buttons.end_frame();
+        buttons.set(GamepadButton::South, true);
+        assert!(!buttons.pressed(false).contains(&GamepadButton::South));
+        assert!(buttons.held.contains(&GamepadButton::South));
+    }
+
+    #[test]
+    fn releasing_everything_is_seen_by_frame_and_step() {
+        let mut buttons = Buttons::default();
+        buttons.set(GamepadButton::South, true);
+        buttons.set(GamepadButton::East, true);
+        buttons.end_frame();
+        buttons.end_step();
+        buttons.release_all();
+        assert!(buttons.held.is_empty());
+        for step in [false, true] {
+            assert_eq!(buttons.released(step).len(), 2);
+        }
+    }
+
+    #[test]
+    fn action_is_pressed_by_the_first_binding_only() {
+        let mut input = input();
+        input.set_gamepad_button(GamepadButton::South, true);
+        assert!(input.action_pressed(JUMP));
+        input.end_frame();
+        // a second binding joining in doesn't press the action again
+        input.set_gamepad_button(GamepadButton::East, true);
+        assert!(!input.action_pressed(JUMP));
+        assert!(input.action_held(JUMP));
+    }
+
+    #[test]
+    fn action_is_released_by_the_last_binding_only() {
+        let mut input = input();
+        input.set_gamepad_button(GamepadButton::South, true);
+        input.set_gamepad_button(GamepadButton::East, true);
+        input.end_frame();
+        input.set_gamepad_button(GamepadButton::South, false);
+        assert!(!input.action_released(JUMP));
+        assert!(input.action_held(JUMP));
+        input.end_frame();
+        input.set_gamepad_button(GamepadButton::East, false);
+        assert!(input.action_released(JUMP));
+        assert!(!input.action_held(JUMP));
+    }
+
+    #[test]
+    fn bindings_pressed_together_press_the_action() {
+        let mut input = input();
+        input.set_gamepad_button(GamepadButton::South, true);
+        input.set_gamepad_button(GamepadButton::East, true);
+        assert!(input.action_pressed(JUMP));
+    }
+
+    #[test]
+    fn tap_within_a_frame_presses_and_releases() {
+        let mut input = input();
+        input.set_gamepad_button(GamepadButton::South, true);
+        input.set_gamepad_button(GamepadButton::South, false);
+        assert!(input.action_pressed(JUMP));
+        assert!(input.action_released(JUMP));
+        assert!(!input.action_held(JUMP));
+    }
+
+    #[test]
+    fn steps_see_presses_from_frames_without_a_step() {
+        let mut input = input();
+        input.set_gamepad_button(GamepadButton::South, true);
+        input.end_frame();
+        assert!(!input.gamepad_pressed(GamepadButton::South));
+        input.begin_step();
+        assert!(input.gamepad_pressed(GamepadButton::South));
+        assert!(input.action_pressed(JUMP));
+        input.end_step();
+    }
+
+    #[test]
+    fn only_the_first_step_of_a_frame_sees_a_press() {
+        let mut input = input();
+        input.set_gamepad_button(GamepadButton::South, true);
+        input.begin_step();
+        assert!(input.action_pressed(JUMP));
+        input.end_step();
+        input.begin_step();
+        assert!(!input.action_pressed(JUMP));
+        assert!(input.action_held(JUMP));
+        input.end_step();
+        // the frame itself still reports it until it ends
+        assert!(input.action_pressed(JUMP));
+    }
+
+    #[test]
+    fn window_events_drive_bindings() {
+        let mut input = Input::default();
+        input
+            .actions
+            .bind("fire", Binding::Mouse(MouseButton::Left));
+        let (window, device_id) = (WindowId::dummy(), DeviceId::dummy());
+        let click = |state| WindowEvent::MouseInput {
+            device_id,
+            state,
+            button: MouseButton::Left,
+        };
+        input.handle_event(window, &click(ElementState::Pressed));
+        assert!(input.action_pressed("fire"));
+        input.end_frame();
+        input.handle_event(window, &WindowEvent::Focused(false));
+        assert!(input.action_released("fire"));
+    }
+}
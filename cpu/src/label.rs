@@ -0,0 +1,46 @@
+//! Debug names for Vulkan objects and labels around command buffer regions, which RenderDoc
+//! captures and validation messages show. Everything here does nothing unless `VK_EXT_debug_utils`
+//! is available.
+use ash::{ext::debug_utils, vk, Device, Instance};
+use std::{ffi::CString, sync::RwLock};
+
+static DEBUG_UTILS: RwLock<Option<debug_utils::Device>> = RwLock::new(None);
+
+/// Names and labels go to `device` until `clear` is called. `instance` must have been created
+/// with `VK_EXT_debug_utils`.
+pub(crate) unsafe fn init(instance: &Instance, device: &Device) {
+    *DEBUG_UTILS.write().unwrap() = Some(debug_utils::Device::new(instance, device));
+}
+
+/// Must be called before the device is destroyed.
+pub(crate) fn clear() {
+    *DEBUG_UTILS.write().unwrap() = None;
+}
+
+pub(crate) unsafe fn name_object(handle: impl vk::Handle, name: &str) {
+    let Some(debug_utils) = &*DEBUG_UTILS.read().unwrap() else {
+        return;
+    };
+    let name = CString::new(name).unwrap();
+    let info = vk::DebugUtilsObjectNameInfoEXT::default()
+        .object_handle(handle)
+        .object_name(&name);
+    // naming is best effort, a failure doesn't affect rendering
+    let _ = debug_utils.set_debug_utils_object_name(&info);
+}
+
+/// Opens a labeled region, which must be closed with `end_label` in the same command buffer.
+pub(crate) unsafe fn begin_label(cmd: vk::CommandBuffer, name: &str) {
+    let Some(debug_utils) = &*DEBUG_UTILS.read().unwrap() else {
+        return;
+    };
+    let name = CString::new(name).unwrap();
+    let label = vk::DebugUtilsLabelEXT::default().label_name(&name);
+    debug_utils.cmd_begin_debug_utils_label(cmd, &label);
+}
+
+pub(crate) unsafe fn end_label(cmd: vk::CommandBuffer) {
+    if let Some(debug_utils) = &*DEBUG_UTILS.read().unwrap() {
+        debug_utils.cmd_end_debug_utils_label(cmd);
+    }
+}
@@ -1,2 +1,44 @@
 mod app;
+mod capture;
+mod config;
+mod debug;
+mod device;
+mod display;
+mod features;
+mod frame;
+mod game;
+mod graph;
+mod input;
+mod label;
+mod pipeline;
+mod profiler;
+mod sprite;
+mod swapchain;
+mod sync;
+mod text;
+mod texture;
+mod time;
+mod tonemap;
+mod ui;
+mod util;
+mod validation;
+mod window;
 pub use app::WrappedApp;
+// the versions of these the public API uses, for games to build against
+pub use capture::CaptureConfig;
+pub use config::{AppConfig, ConfigError, WindowConfig, ARGS_USAGE, MAX_FRAMES_IN_FLIGHT};
+pub use debug::{debug_axes, debug_box, debug_grid, debug_line, debug_sphere, DebugShape};
+pub use device::{GpuSelector, GPU_ENV};
+pub use display::{DisplayConfig, MonitorSelector, VideoModeRequest, WindowMode};
+pub use features::DeviceCapabilities;
+pub use game::{Context, Frame, Game};
+pub use input::{ActionMap, Binding, GamepadButton, Input, KeyCode, ModifiersState, MouseButton};
+pub use profiler::FrameStats;
+pub use sprite::{Sprite, SpriteBatch};
+pub use swapchain::{PresentMode, SurfaceSupport, SwapchainConfig};
+pub use text::{FontId, Fonts, TextBatch, TextStyle};
+pub use texture::TextureId;
+pub use time::{Clock, RedrawMode};
+pub use tonemap::OutputMode;
+pub use validation::{ValidationConfig, ValidationSeverity, VALIDATION_ENV};
+pub use {egui, glam, winit};
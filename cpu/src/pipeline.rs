@@ -0,0 +1,159 @@
+use crate::label::name_object;
+use ash::{khr::dynamic_rendering, vk, Device};
+use std::ffi::CStr;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Blend {
+    /// Straight alpha, for colors that have not been multiplied by their alpha.
+    Alpha,
+    Premultiplied,
+}
+
+/// How passes are begun: with render pass and framebuffer objects on devices without dynamic
+/// rendering, or with `cmd_begin_rendering`, from Vulkan 1.3 or `VK_KHR_dynamic_rendering`.
+#[derive(Default)]
+pub(crate) enum Rendering {
+    #[default]
+    RenderPass,
+    Core,
+    Khr(dynamic_rendering::Device),
+}
+
+impl Rendering {
+    pub fn is_dynamic(&self) -> bool {
+        !matches!(self, Rendering::RenderPass)
+    }
+
+    pub unsafe fn begin(&self, device: &Device, cmd: vk::CommandBuffer, info: &vk::RenderingInfo) {
+        match self {
+            Rendering::RenderPass => panic!("Dynamic rendering is not enabled."),
+            Rendering::Core => device.cmd_begin_rendering(cmd, info),
+            Rendering::Khr(loader) => loader.cmd_begin_rendering(cmd, info),
+        }
+    }
+
+    pub unsafe fn end(&self, device: &Device, cmd: vk::CommandBuffer) {
+        match self {
+            Rendering::RenderPass => panic!("Dynamic rendering is not enabled."),
+            Rendering::Core => device.cmd_end_rendering(cmd),
+            Rendering::Khr(loader) => loader.cmd_end_rendering(cmd),
+        }
+    }
+}
+
+/// What a pipeline draws into. With dynamic rendering there is no render pass, only the formats of
+/// the attachments.
+#[derive(Clone, Copy)]
+pub(crate) enum PassTarget {
+    RenderPass(vk::RenderPass),
+    Dynamic {
+        color: vk::Format,
+        /// `UNDEFINED` without a depth attachment.
+        depth: vk::Format,
+    },
+}
+
+/// The parts of a graphics pipeline that differ between the renderer's pipelines. Everything
+/// else (dynamic viewport / scissor, single sample, blending enabled) is shared.
+pub(crate) struct PipelineDesc<'a> {
+    /// For debugging tools.
+    pub name: &'a str,
+    pub vert: &'a CStr,
+    pub frag: &'a CStr,
+    pub bindings: &'a [vk::VertexInputBindingDescription],
+    pub attrs: &'a [vk::VertexInputAttributeDescription],
+    pub topology: vk::PrimitiveTopology,
+    pub cull_mode: vk::CullModeFlags,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub blend: Blend,
+}
+
+pub(crate) unsafe fn create_graphics_pipeline(
+    device: &Device,
+    shader_module: vk::ShaderModule,
+    target: PassTarget,
+    layout: vk::PipelineLayout,
+    desc: &PipelineDesc,
+) -> vk::Pipeline {
+    let shader_stage = |name, flags| {
+        vk::PipelineShaderStageCreateInfo::default()
+            .stage(flags)
+            .module(shader_module)
+            .name(name)
+    };
+    let shader_stage_info = [
+        shader_stage(desc.vert, vk::ShaderStageFlags::VERTEX),
+        shader_stage(desc.frag, vk::ShaderStageFlags::FRAGMENT),
+    ];
+    let vert_in_info = vk::PipelineVertexInputStateCreateInfo::default()
+        .vertex_binding_descriptions(desc.bindings)
+        .vertex_attribute_descriptions(desc.attrs);
+    let dyn_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dyn_state_info = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dyn_states);
+    let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::default()
+        .topology(desc.topology)
+        .primitive_restart_enable(false);
+    let viewport_info = vk::PipelineViewportStateCreateInfo::default()
+        .viewport_count(1)
+        .scissor_count(1);
+    let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::default()
+        .depth_clamp_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(desc.cull_mode)
+        .front_face(vk::FrontFace::CLOCKWISE)
+        .depth_bias_enable(false);
+    let multisampling_info = vk::PipelineMultisampleStateCreateInfo::default()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+    let depth_info = vk::PipelineDepthStencilStateCreateInfo::default()
+        .depth_test_enable(desc.depth_test)
+        .depth_write_enable(desc.depth_write)
+        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL);
+    let blending_attachment = [vk::PipelineColorBlendAttachmentState::default()
+        .color_write_mask(vk::ColorComponentFlags::RGBA)
+        .blend_enable(true)
+        .src_color_blend_factor(match desc.blend {
+            Blend::Alpha => vk::BlendFactor::SRC_ALPHA,
+            Blend::Premultiplied => vk::BlendFactor::ONE,
+        })
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .alpha_blend_op(vk::BlendOp::ADD)];
+    let blending_info = vk::PipelineColorBlendStateCreateInfo::default()
+        .logic_op_enable(false)
+        .attachments(&blending_attachment);
+    let mut pipeline_info = vk::GraphicsPipelineCreateInfo::default()
+        .stages(&shader_stage_info)
+        .vertex_input_state(&vert_in_info)
+        .input_assembly_state(&input_assembly_info)
+        .viewport_state(&viewport_info)
+        .rasterization_state(&rasterizer_info)
+        .multisample_state(&multisampling_info)
+        .depth_stencil_state(&depth_info)
+        .color_blend_state(&blending_info)
+        .dynamic_state(&dyn_state_info)
+        .layout(layout);
+    let color_formats;
+    let mut rendering_info;
+    match target {
+        PassTarget::RenderPass(render_pass) => {
+            pipeline_info = pipeline_info.render_pass(render_pass).subpass(0);
+        }
+        PassTarget::Dynamic { color, depth } => {
+            color_formats = [color];
+            rendering_info = vk::PipelineRenderingCreateInfo::default()
+                .color_attachment_formats(&color_formats)
+                .depth_attachment_format(depth);
+            pipeline_info = pipeline_info.push_next(&mut rendering_info);
+        }
+    }
+    let pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+        .expect("Failed to create graphics pipeline.")[0];
+    name_object(pipeline, desc.name);
+    pipeline
+}
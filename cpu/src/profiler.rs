@@ -0,0 +1,292 @@
+//! Frame timing statistics. CPU frame times are measured directly, GPU scopes with timestamp
+//! queries that are read back once their frame has been waited on, so reading never
+//! stalls.
+use crate::{
+    frame::{FrameContext, FrameData},
+    label::{begin_label, end_label},
+};
+use ash::{vk, Device};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Write},
+};
+
+/// Samples kept per series for averages and percentiles.
+const HISTORY_LEN: usize = 240;
+/// Timestamps per frame; each scope uses two.
+const MAX_QUERIES: u32 = 64;
+
+/// The last `HISTORY_LEN` samples of one measurement, in milliseconds.
+#[derive(Default)]
+struct History {
+    samples: VecDeque<f32>,
+}
+
+impl History {
+    fn push(&mut self, ms: f32) {
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(ms);
+    }
+
+    fn average(&self) -> Option<f32> {
+        (!self.samples.is_empty())
+            .then(|| self.samples.iter().sum::<f32>() / self.samples.len() as f32)
+    }
+
+    /// Nearest rank percentile, `p` from 0 to 100.
+    fn percentile(&self, p: f32) -> Option<f32> {
+        let mut sorted: Vec<f32> = self.samples.iter().copied().collect();
+        sorted.sort_by(f32::total_cmp);
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * sorted.len() as f32).ceil() as usize;
+        sorted.get(rank.saturating_sub(1)).copied()
+    }
+
+    fn write_json(&self, w: &mut impl Write) -> io::Result<()> {
+        let stat = |v: Option<f32>| v.map_or("null".to_owned(), |v| v.to_string());
+        write!(
+            w,
+            "{{\"avg\":{},\"p50\":{},\"p95\":{},\"p99\":{},\"samples\":[",
+            stat(self.average()),
+            stat(self.percentile(50.0)),
+            stat(self.percentile(95.0)),
+            stat(self.percentile(99.0)),
+        )?;
+        for (i, ms) in self.samples.iter().enumerate() {
+            write!(w, "{}{}", if i == 0 { "" } else { "," }, ms)?;
+        }
+        write!(w, "]}}")
+    }
+}
+
+/// Rolling CPU frame times and GPU scope times, in milliseconds.
+#[derive(Default)]
+pub struct FrameStats {
+    cpu: History,
+    gpu: HashMap<String, History>,
+}
+
+impl FrameStats {
+    pub(crate) fn record_cpu(&mut self, ms: f32) {
+        self.cpu.push(ms);
+    }
+
+    fn record_gpu(&mut self, scope: &str, ms: f32) {
+        self.gpu.entry(scope.to_owned()).or_default().push(ms);
+    }
+
+    /// Average CPU time spent on a frame.
+    pub fn cpu_ms(&self) -> Option<f32> {
+        self.cpu.average()
+    }
+
+    /// CPU frame time percentile, `p` from 0 to 100.
+    pub fn cpu_ms_percentile(&self, p: f32) -> Option<f32> {
+        self.cpu.percentile(p)
+    }
+
+    /// Average GPU time of a scope, e.g. "frame", "scene" or "ui".
+    pub fn gpu_ms(&self, scope: &str) -> Option<f32> {
+        self.gpu.get(scope)?.average()
+    }
+
+    pub fn gpu_ms_percentile(&self, scope: &str, p: f32) -> Option<f32> {
+        self.gpu.get(scope)?.percentile(p)
+    }
+
+    /// Names of every GPU scope measured so far.
+    pub fn gpu_scopes(&self) -> impl Iterator<Item = &str> {
+        self.gpu.keys().map(String::as_str)
+    }
+
+    fn series(&self) -> impl Iterator<Item = (&str, &History)> {
+        let mut gpu: Vec<_> = self.gpu.iter().collect();
+        gpu.sort_by_key(|(name, _)| *name);
+        std::iter::once(("cpu", &self.cpu)).chain(gpu.into_iter().map(|(n, h)| (n.as_str(), h)))
+    }
+
+    /// Writes every sample as `series,sample,ms` rows, with GPU scopes named `gpu:<scope>`.
+    pub fn write_csv(&self, mut w: impl Write) -> io::Result<()> {
+        writeln!(w, "series,sample,ms")?;
+        for (i, (name, history)) in self.series().enumerate() {
+            let prefix = if i == 0 { "" } else { "gpu:" };
+            for (sample, ms) in history.samples.iter().enumerate() {
+                writeln!(w, "{prefix}{name},{sample},{ms}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes averages, percentiles and samples of every series as a JSON object.
+    pub fn write_json(&self, mut w: impl Write) -> io::Result<()> {
+        write!(w, "{{\"cpu\":")?;
+        self.cpu.write_json(&mut w)?;
+        write!(w, ",\"gpu\":{{")?;
+        for (i, (name, history)) in self.series().skip(1).enumerate() {
+            // scope names are chosen by the renderer and callers, escape the quoting characters
+            let name = name.replace('\\', "\\\\").replace('"', "\\\"");
+            write!(w, "{}\"{name}\":", if i == 0 { "" } else { "," })?;
+            history.write_json(&mut w)?;
+        }
+        writeln!(w, "}}}}")
+    }
+}
+
+/// A scope's pair of timestamp queries.
+struct Scope {
+    name: String,
+    start: u32,
+    end: Option<u32>,
+}
+
+/// A frame's timestamp queries and the scopes they belong to.
+struct ProfilerFrame {
+    pool: vk::QueryPool,
+    scopes: Vec<Scope>,
+}
+
+impl FrameData for ProfilerFrame {
+    unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_query_pool(self.pool, None);
+    }
+}
+
+/// Timestamp queries for GPU scopes, with a query pool per frame in flight.
+#[derive(Default)]
+pub(crate) struct GpuProfiler {
+    /// Indices into the frame's scopes of the scopes begun but not yet ended.
+    open: Vec<usize>,
+    queries_used: u32,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+    valid_bits: u32,
+}
+
+impl GpuProfiler {
+    /// Profiling is disabled, with every call doing nothing, if the queue can't write timestamps.
+    pub fn new(limits: &vk::PhysicalDeviceLimits, valid_bits: u32) -> Self {
+        if valid_bits == 0 || limits.timestamp_period == 0.0 {
+            return GpuProfiler::default();
+        }
+        GpuProfiler {
+            period: limits.timestamp_period,
+            valid_bits,
+            ..Default::default()
+        }
+    }
+
+    /// Gives each of `frames` the query pool its timestamps are written to.
+    pub unsafe fn attach_frames(&self, device: &Device, frames: &mut [FrameContext]) {
+        if !self.enabled() {
+            return;
+        }
+        let info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(MAX_QUERIES);
+        for frame in frames.iter_mut() {
+            frame.attach(ProfilerFrame {
+                pool: device
+                    .create_query_pool(&info, None)
+                    .expect("Failed to create timestamp query pool."),
+                scopes: Vec::new(),
+            });
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.valid_bits != 0
+    }
+
+    /// Reads the timestamps `frame` wrote the last time around into `stats`. Call after waiting
+    /// for the frame.
+    pub unsafe fn collect(
+        &mut self,
+        device: &Device,
+        frame: &mut FrameContext,
+        stats: &mut FrameStats,
+    ) {
+        if !self.enabled() {
+            return;
+        }
+        let frame = frame.get_mut::<ProfilerFrame>();
+        let scopes = std::mem::take(&mut frame.scopes);
+        let Some(count) = scopes.iter().filter_map(|s| s.end).max().map(|q| q + 1) else {
+            return;
+        };
+        let mut ticks = vec![0u64; count as usize];
+        // only NOT_READY can fail here, which would mean the frame was not waited on
+        if device
+            .get_query_pool_results(frame.pool, 0, &mut ticks, vk::QueryResultFlags::TYPE_64)
+            .is_err()
+        {
+            return;
+        }
+        let mask = if self.valid_bits >= 64 {
+            u64::MAX
+        } else {
+            (1 << self.valid_bits) - 1
+        };
+        for scope in scopes {
+            if let Some(end) = scope.end {
+                let elapsed = ticks[end as usize].wrapping_sub(ticks[scope.start as usize]) & mask;
+                stats.record_gpu(&scope.name, elapsed as f32 * self.period / 1.0e6);
+            }
+        }
+    }
+
+    /// Resets the frame's queries. Must be recorded before any scope, outside a render pass.
+    pub unsafe fn reset(&mut self, device: &Device, frame: &FrameContext) {
+        self.queries_used = 0;
+        self.open.clear();
+        if self.enabled() {
+            let pool = frame.get::<ProfilerFrame>().pool;
+            device.cmd_reset_query_pool(frame.cmd, pool, 0, MAX_QUERIES);
+        }
+    }
+
+    /// Starts timing `name`, and labels the scope for debugging tools. Scopes can nest and must be
+    /// closed with `end` in the same frame.
+    pub unsafe fn begin(&mut self, device: &Device, frame: &mut FrameContext, name: &str) {
+        begin_label(frame.cmd, name);
+        // reserve the end query as well, so a started scope can always be ended
+        if !self.enabled() || self.queries_used + 2 > MAX_QUERIES {
+            self.open.push(usize::MAX);
+            return;
+        }
+        let cmd = frame.cmd;
+        let frame = frame.get_mut::<ProfilerFrame>();
+        device.cmd_write_timestamp(
+            cmd,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            frame.pool,
+            self.queries_used,
+        );
+        self.open.push(frame.scopes.len());
+        frame.scopes.push(Scope {
+            name: name.to_owned(),
+            start: self.queries_used,
+            end: None,
+        });
+        self.queries_used += 2;
+    }
+
+    pub unsafe fn end(&mut self, device: &Device, frame: &mut FrameContext) {
+        end_label(frame.cmd);
+        let scope = self
+            .open
+            .pop()
+            .expect("GPU scope ended without being begun.");
+        if !self.enabled() {
+            return;
+        }
+        let cmd = frame.cmd;
+        let frame = frame.get_mut::<ProfilerFrame>();
+        let Some(scope) = frame.scopes.get_mut(scope) else {
+            return;
+        };
+        let end = scope.start + 1;
+        device.cmd_write_timestamp(cmd, vk::PipelineStageFlags::BOTTOM_OF_PIPE, frame.pool, end);
+        scope.end = Some(end);
+    }
+}
@@ -0,0 +1,393 @@
+use crate::{
+    frame::{FrameContext, FrameData},
+    pipeline::{create_graphics_pipeline, Blend, PassTarget, PipelineDesc},
+    profiler::GpuProfiler,
+    texture::{TextureId, Textures},
+    util::{DynBuffer, Gpu},
+};
+use ash::{vk, Device};
+use glam::{vec2, Mat4, Vec2, Vec4};
+
+/// The two triangles of a quad whose corners are in the order top left, top right, bottom right,
+/// bottom left.
+pub(crate) const INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
+
+/// A textured, tinted and rotated quad in pixel coordinates, with the origin in the top left
+/// corner of the window.
+#[derive(Clone, Copy, Debug)]
+pub struct Sprite {
+    pub pos: Vec2,
+    pub size: Vec2,
+    /// Point the sprite rotates around, relative to its size. `(0.5, 0.5)` is the center.
+    pub origin: Vec2,
+    /// Rotation in radians, clockwise on screen.
+    pub rotation: f32,
+    /// Texture coordinates of the top left and bottom right corners.
+    pub uv: [Vec2; 2],
+    pub color: Vec4,
+    /// Tints of the individual corners, in the order top left, top right, bottom right, bottom
+    /// left, on top of `color`.
+    pub corner_colors: [Vec4; 4],
+    /// Sprites on lower layers are drawn first.
+    pub layer: i32,
+    pub texture: TextureId,
+}
+
+impl Sprite {
+    pub fn new(texture: TextureId, pos: Vec2, size: Vec2) -> Self {
+        Sprite {
+            pos,
+            size,
+            origin: Vec2::ZERO,
+            rotation: 0.0,
+            uv: [Vec2::ZERO, Vec2::ONE],
+            color: Vec4::ONE,
+            corner_colors: [Vec4::ONE; 4],
+            layer: 0,
+            texture,
+        }
+    }
+
+    pub fn with_origin(self, origin: Vec2) -> Self {
+        Sprite { origin, ..self }
+    }
+
+    pub fn with_rotation(self, rotation: f32) -> Self {
+        Sprite { rotation, ..self }
+    }
+
+    pub fn with_uv(self, min: Vec2, max: Vec2) -> Self {
+        Sprite {
+            uv: [min, max],
+            ..self
+        }
+    }
+
+    pub fn with_color(self, color: Vec4) -> Self {
+        Sprite { color, ..self }
+    }
+
+    pub fn with_corner_colors(self, corner_colors: [Vec4; 4]) -> Self {
+        Sprite {
+            corner_colors,
+            ..self
+        }
+    }
+
+    pub fn with_layer(self, layer: i32) -> Self {
+        Sprite { layer, ..self }
+    }
+
+    fn vertices(&self) -> [SpriteVertex; 4] {
+        let (sin, cos) = self.rotation.sin_cos();
+        let pivot = self.origin * self.size;
+        // in the order `INDICES` expects
+        let corners = [
+            vec2(0.0, 0.0),
+            vec2(1.0, 0.0),
+            vec2(1.0, 1.0),
+            vec2(0.0, 1.0),
+        ];
+        let mut colors = self.corner_colors.into_iter();
+        corners.map(|c| {
+            let col = self.color * colors.next().unwrap();
+            let local = c * self.size - pivot;
+            SpriteVertex {
+                pos: self.pos + vec2(local.x * cos - local.y * sin, local.x * sin + local.y * cos),
+                uv: self.uv[0] + c * (self.uv[1] - self.uv[0]),
+                col,
+            }
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub(crate) struct SpriteVertex {
+    pub pos: Vec2,
+    pub uv: Vec2,
+    pub col: Vec4,
+}
+
+impl SpriteVertex {
+    pub fn binding_descr() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription::default()
+            .stride(size_of::<SpriteVertex>() as u32)
+            .binding(0)
+            .input_rate(vk::VertexInputRate::VERTEX)]
+    }
+
+    pub fn attr_descr() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription::default()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(core::mem::offset_of!(SpriteVertex, pos) as u32),
+            vk::VertexInputAttributeDescription::default()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(core::mem::offset_of!(SpriteVertex, uv) as u32),
+            vk::VertexInputAttributeDescription::default()
+                .binding(0)
+                .location(2)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(core::mem::offset_of!(SpriteVertex, col) as u32),
+        ]
+    }
+}
+
+/// Sprites queued for the current frame. Cleared every time the renderer draws it.
+#[derive(Default)]
+pub struct SpriteBatch {
+    sprites: Vec<Sprite>,
+    /// GPU scopes opened, with their name, and closed, with `None`, at the number of sprites
+    /// queued by then. Sorting never moves a sprite across one.
+    scopes: Vec<(usize, Option<String>)>,
+}
+
+impl SpriteBatch {
+    pub fn draw(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite);
+    }
+
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+        self.scopes.clear();
+    }
+
+    pub(crate) fn begin_scope(&mut self, name: &str) {
+        self.scopes
+            .push((self.sprites.len(), Some(name.to_owned())));
+    }
+
+    pub(crate) fn end_scope(&mut self) {
+        self.scopes.push((self.sprites.len(), None));
+    }
+}
+
+/// The vertex buffer each frame in flight writes its sprites to.
+struct SpriteFrame {
+    vert_buff: DynBuffer,
+}
+
+impl FrameData for SpriteFrame {
+    unsafe fn destroy(&mut self, device: &Device) {
+        self.vert_buff.destroy(device);
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct SpriteRenderer {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    ind_buff: (vk::Buffer, vk::DeviceMemory),
+    /// Number of sprites the index buffer has room for.
+    ind_capacity: usize,
+    /// Index buffers outgrown, destroyed once the numbered frame that last used them is done.
+    retired: Vec<(u64, (vk::Buffer, vk::DeviceMemory))>,
+    vertices: Vec<SpriteVertex>,
+}
+
+impl SpriteRenderer {
+    const INITIAL_CAPACITY: usize = 1024;
+
+    pub unsafe fn new(
+        gpu: Gpu,
+        shader_module: vk::ShaderModule,
+        target: PassTarget,
+        textures: &Textures,
+    ) -> Self {
+        let device = gpu.device;
+        let set_layouts = [textures.set_layout];
+        let push_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .size(size_of::<Mat4>() as u32)];
+        let layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_ranges);
+        let pipeline_layout = device
+            .create_pipeline_layout(&layout_info, None)
+            .expect("Failed to create sprite pipeline layout.");
+        let pipeline = create_graphics_pipeline(
+            device,
+            shader_module,
+            target,
+            pipeline_layout,
+            &PipelineDesc {
+                name: "sprites",
+                vert: c"sprite_vert",
+                frag: c"sprite_frag",
+                bindings: &SpriteVertex::binding_descr(),
+                attrs: &SpriteVertex::attr_descr(),
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_test: false,
+                depth_write: false,
+                blend: Blend::Alpha,
+            },
+        );
+        SpriteRenderer {
+            pipeline_layout,
+            pipeline,
+            ind_buff: Self::make_indices(gpu, Self::INITIAL_CAPACITY),
+            ind_capacity: Self::INITIAL_CAPACITY,
+            retired: Vec::new(),
+            vertices: Vec::new(),
+        }
+    }
+
+    /// Gives each of `frames` the vertex buffer it writes its sprites to.
+    pub unsafe fn attach_frames(gpu: Gpu, frames: &mut [FrameContext]) {
+        for frame in frames.iter_mut() {
+            frame.attach(SpriteFrame {
+                vert_buff: DynBuffer::new(
+                    gpu,
+                    Self::INITIAL_CAPACITY * 4 * size_of::<SpriteVertex>(),
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    "sprite vertices",
+                ),
+            });
+        }
+    }
+
+    unsafe fn make_indices(gpu: Gpu, sprites: usize) -> (vk::Buffer, vk::DeviceMemory) {
+        let indices: Vec<u32> = (0..sprites as u32)
+            .flat_map(|i| INDICES.map(|ind| ind as u32 + 4 * i))
+            .collect();
+        gpu.upload_buffer(
+            &indices,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            "sprite indices",
+        )
+    }
+
+    /// Makes room for `sprites` sprites in the index buffer, before recording any frame that
+    /// draws them. `submitted` is the number of the last frame submitted, and index buffers
+    /// outgrown by frames up to `completed` are destroyed.
+    pub unsafe fn reserve(&mut self, gpu: Gpu, sprites: usize, submitted: u64, completed: u64) {
+        self.retired.retain(|&(last_used, (buffer, memory))| {
+            let done = last_used <= completed;
+            if done {
+                gpu.device.destroy_buffer(buffer, None);
+                gpu.device.free_memory(memory, None);
+            }
+            !done
+        });
+        if sprites > self.ind_capacity {
+            // the index buffer is shared between frames in flight, which may still read the old one
+            self.retired.push((submitted, self.ind_buff));
+            self.ind_capacity = sprites.next_power_of_two();
+            self.ind_buff = Self::make_indices(gpu, self.ind_capacity);
+        }
+    }
+
+    /// Sorts `batch` by layer and texture, uploads it into this frame's vertex buffer and records
+    /// one draw per run of sprites sharing a texture, with the batch's scopes around them. Must be
+    /// called inside the render pass, after `reserve` for the batch.
+    pub unsafe fn flush(
+        &mut self,
+        gpu: Gpu,
+        frame: &mut FrameContext,
+        batch: &mut SpriteBatch,
+        textures: &Textures,
+        extent: vk::Extent2D,
+        profiler: &mut GpuProfiler,
+    ) {
+        if batch.is_empty() {
+            // empty scopes are still recorded, so they show up in the stats every frame
+            for (_, scope) in batch.scopes.drain(..) {
+                match scope {
+                    Some(name) => profiler.begin(gpu.device, frame, &name),
+                    None => profiler.end(gpu.device, frame),
+                }
+            }
+            return;
+        }
+        let device = gpu.device;
+        let cmd = frame.cmd;
+        // stable, so sprites on the same layer and texture keep their submission order
+        let mut start = 0;
+        for end in batch.scopes.iter().map(|(at, _)| *at).chain([batch.len()]) {
+            batch.sprites[start..end].sort_by_key(|s| (s.layer, s.texture));
+            start = end;
+        }
+        let count = batch.len();
+        assert!(
+            count <= self.ind_capacity,
+            "Sprite index buffer wasn't reserved for the batch."
+        );
+        self.vertices.clear();
+        self.vertices
+            .extend(batch.sprites.iter().flat_map(Sprite::vertices));
+        let vert_buff = &mut frame.get_mut::<SpriteFrame>().vert_buff;
+        vert_buff.write(gpu, &self.vertices);
+        let vert_buff = vert_buff.buff;
+
+        let proj = Mat4::orthographic_rh(
+            0.0,
+            extent.width as f32,
+            0.0,
+            extent.height as f32,
+            -1.0,
+            1.0,
+        );
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_push_constants(
+            cmd,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            bytemuck::cast_slice(&proj.to_cols_array()),
+        );
+        device.cmd_bind_vertex_buffers(cmd, 0, &[vert_buff], &[0]);
+        device.cmd_bind_index_buffer(cmd, self.ind_buff.0, 0, vk::IndexType::UINT32);
+        let mut scopes = std::mem::take(&mut batch.scopes).into_iter().peekable();
+        let mut start = 0;
+        loop {
+            while let Some((_, scope)) = scopes.next_if(|(at, _)| *at == start) {
+                match scope {
+                    Some(name) => profiler.begin(device, frame, &name),
+                    None => profiler.end(device, frame),
+                }
+            }
+            if start == count {
+                break;
+            }
+            let end = scopes.peek().map_or(count, |(at, _)| *at);
+            for run in batch.sprites[start..end].chunk_by(|a, b| a.texture == b.texture) {
+                device.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    0,
+                    &[textures.get(run[0].texture).set],
+                    &[],
+                );
+                device.cmd_draw_indexed(cmd, run.len() as u32 * 6, 1, start as u32 * 6, 0, 0);
+                start += run.len();
+            }
+        }
+        batch.clear();
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        for &(_, (buffer, memory)) in &self.retired {
+            device.destroy_buffer(buffer, None);
+            device.free_memory(memory, None);
+        }
+        device.destroy_buffer(self.ind_buff.0, None);
+        device.free_memory(self.ind_buff.1, None);
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+    }
+}
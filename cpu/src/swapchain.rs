@@ -0,0 +1,116 @@
+use ash::vk;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// How presented images are synchronized with the display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PresentMode {
+    /// Vsync. Always supported.
+    Fifo,
+    /// Vsync, but late frames are shown immediately and may tear.
+    FifoRelaxed,
+    /// Uncapped rendering where only the newest image is shown, without tearing.
+    Mailbox,
+    /// Uncapped rendering that may tear.
+    Immediate,
+}
+
+impl PresentMode {
+    pub(crate) fn to_vk(self) -> vk::PresentModeKHR {
+        match self {
+            PresentMode::Fifo => vk::PresentModeKHR::FIFO,
+            PresentMode::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+            PresentMode::Mailbox => vk::PresentModeKHR::MAILBOX,
+            PresentMode::Immediate => vk::PresentModeKHR::IMMEDIATE,
+        }
+    }
+
+    fn from_vk(mode: vk::PresentModeKHR) -> Option<Self> {
+        match mode {
+            vk::PresentModeKHR::FIFO => Some(PresentMode::Fifo),
+            vk::PresentModeKHR::FIFO_RELAXED => Some(PresentMode::FifoRelaxed),
+            vk::PresentModeKHR::MAILBOX => Some(PresentMode::Mailbox),
+            vk::PresentModeKHR::IMMEDIATE => Some(PresentMode::Immediate),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for PresentMode {
+    type Err = String;
+
+    /// The names used in config files: `fifo`, `fifo-relaxed`, `mailbox` or `immediate`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "fifo" => Ok(PresentMode::Fifo),
+            "fifo-relaxed" => Ok(PresentMode::FifoRelaxed),
+            "mailbox" => Ok(PresentMode::Mailbox),
+            "immediate" => Ok(PresentMode::Immediate),
+            _ => Err(format!(
+                "unknown present mode {s:?}, expected fifo, fifo-relaxed, mailbox or immediate"
+            )),
+        }
+    }
+}
+
+/// The requested swapchain setup. Requests the surface can't satisfy are adjusted: unsupported
+/// present modes fall back to `Fifo` and the image count is clamped to the surface's limits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SwapchainConfig {
+    pub present_mode: PresentMode,
+    /// Defaults to one more than the surface's minimum.
+    pub image_count: Option<u32>,
+}
+
+impl Default for SwapchainConfig {
+    fn default() -> Self {
+        SwapchainConfig {
+            present_mode: PresentMode::Mailbox,
+            image_count: None,
+        }
+    }
+}
+
+/// What the window's surface supports.
+#[derive(Clone, Debug)]
+pub struct SurfaceSupport {
+    pub present_modes: Vec<PresentMode>,
+    pub min_image_count: u32,
+    /// `None` if there is no upper limit.
+    pub max_image_count: Option<u32>,
+}
+
+impl SurfaceSupport {
+    pub(crate) fn new(
+        capabilities: &vk::SurfaceCapabilitiesKHR,
+        modes: &[vk::PresentModeKHR],
+    ) -> Self {
+        SurfaceSupport {
+            present_modes: modes
+                .iter()
+                .filter_map(|m| PresentMode::from_vk(*m))
+                .collect(),
+            min_image_count: capabilities.min_image_count,
+            max_image_count: (capabilities.max_image_count != 0)
+                .then_some(capabilities.max_image_count),
+        }
+    }
+
+    pub(crate) fn present_mode(&self, config: &SwapchainConfig) -> PresentMode {
+        if self.present_modes.contains(&config.present_mode) {
+            config.present_mode
+        } else {
+            PresentMode::Fifo
+        }
+    }
+
+    pub(crate) fn image_count(&self, config: &SwapchainConfig) -> u32 {
+        let count = config
+            .image_count
+            .unwrap_or(self.min_image_count + 1)
+            .max(self.min_image_count);
+        self.max_image_count.map_or(count, |max| count.min(max))
+    }
+}
@@ -0,0 +1,160 @@
+//! Frame synchronization. Frames are numbered from 1 as they are submitted. With Vulkan 1.2 a
+//! timeline semaphore is signaled with each frame's number once it finishes, so anything can wait
+//! for the frame that last used a resource; with 1.3 submits and barriers also use
+//! synchronization2. Older devices fall back to each frame context's fence.
+use crate::frame::FrameContext;
+use ash::{vk, Device};
+
+#[derive(Default)]
+pub(crate) struct FrameSync {
+    /// Null on the fence fallback.
+    timeline: vk::Semaphore,
+    submitted: u64,
+    sync2: bool,
+}
+
+impl FrameSync {
+    /// `timeline` and `sync2` must only be set if the device was created with those features.
+    pub unsafe fn new(device: &Device, timeline: bool, sync2: bool) -> Self {
+        let mut sync = FrameSync {
+            sync2: sync2 && timeline,
+            ..Default::default()
+        };
+        if timeline {
+            let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+                .semaphore_type(vk::SemaphoreType::TIMELINE)
+                .initial_value(0);
+            sync.timeline = device
+                .create_semaphore(
+                    &vk::SemaphoreCreateInfo::default().push_next(&mut type_info),
+                    None,
+                )
+                .expect("Failed to create timeline semaphore.");
+        }
+        sync
+    }
+
+    /// Whether frame contexts need their own fences.
+    pub fn uses_fences(&self) -> bool {
+        self.timeline == vk::Semaphore::null()
+    }
+
+    /// Whether barriers and submits should use synchronization2.
+    pub fn sync2(&self) -> bool {
+        self.sync2
+    }
+
+    /// The number of the last submitted frame.
+    pub fn submitted(&self) -> u64 {
+        self.submitted
+    }
+
+    /// The number of the last frame whose commands have finished. `frames` are all frame contexts
+    /// submitted with, those of every window.
+    pub unsafe fn completed<'a>(
+        &self,
+        device: &Device,
+        frames: impl IntoIterator<Item = &'a FrameContext>,
+    ) -> u64 {
+        if !self.uses_fences() {
+            return device
+                .get_semaphore_counter_value(self.timeline)
+                .expect("Failed to get timeline semaphore value.");
+        }
+        // frames finish in submission order, so everything before the oldest pending one is done
+        frames
+            .into_iter()
+            .filter(|f| f.submitted != 0)
+            .filter(|f| !device.get_fence_status(f.fence).unwrap_or(false))
+            .map(|f| f.submitted - 1)
+            .min()
+            .unwrap_or(self.submitted)
+    }
+
+    /// Blocks until the frame last submitted with `frame` has finished, so its resources can be
+    /// reused.
+    pub unsafe fn wait(&self, device: &Device, frame: &FrameContext) {
+        if frame.submitted == 0 {
+            return;
+        }
+        if self.uses_fences() {
+            device
+                .wait_for_fences(&[frame.fence], true, u64::MAX)
+                .expect("Failed to wait for fences.");
+        } else {
+            let semaphores = [self.timeline];
+            let values = [frame.submitted];
+            let wait_info = vk::SemaphoreWaitInfo::default()
+                .semaphores(&semaphores)
+                .values(&values);
+            device
+                .wait_semaphores(&wait_info, u64::MAX)
+                .expect("Failed to wait for timeline semaphore.");
+        }
+    }
+
+    /// Submits the frame's command buffer, once its image is acquired, as the next numbered frame.
+    pub unsafe fn submit(&mut self, device: &Device, queue: vk::Queue, frame: &mut FrameContext) {
+        self.submitted += 1;
+        frame.submitted = self.submitted;
+        let image_available = [frame.image_available];
+        let cmds = [frame.cmd];
+        if self.sync2 {
+            let waits = [vk::SemaphoreSubmitInfo::default()
+                .semaphore(frame.image_available)
+                .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)];
+            let signals = [
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(frame.render_done)
+                    .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS),
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(self.timeline)
+                    .value(self.submitted)
+                    .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS),
+            ];
+            let cmd_infos = [vk::CommandBufferSubmitInfo::default().command_buffer(frame.cmd)];
+            let submit_info = vk::SubmitInfo2::default()
+                .wait_semaphore_infos(&waits)
+                .signal_semaphore_infos(&signals)
+                .command_buffer_infos(&cmd_infos);
+            device
+                .queue_submit2(queue, &[submit_info], vk::Fence::null())
+                .expect("Failed to submit frame.");
+        } else if !self.uses_fences() {
+            let signals = [frame.render_done, self.timeline];
+            // the binary semaphores' values are ignored
+            let wait_values = [0];
+            let signal_values = [0, self.submitted];
+            let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::default()
+                .wait_semaphore_values(&wait_values)
+                .signal_semaphore_values(&signal_values);
+            let submit_info = vk::SubmitInfo::default()
+                .wait_semaphores(&image_available)
+                .signal_semaphores(&signals)
+                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
+                .command_buffers(&cmds)
+                .push_next(&mut timeline_info);
+            device
+                .queue_submit(queue, &[submit_info], vk::Fence::null())
+                .expect("Failed to submit frame.");
+        } else {
+            // reset only now that the frame is sure to be submitted, or waiting would deadlock
+            device
+                .reset_fences(&[frame.fence])
+                .expect("Failed to reset fences.");
+            let render_done = [frame.render_done];
+            let submit_info = vk::SubmitInfo::default()
+                .wait_semaphores(&image_available)
+                .signal_semaphores(&render_done)
+                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
+                .command_buffers(&cmds);
+            device
+                .queue_submit(queue, &[submit_info], frame.fence)
+                .expect("Failed to submit frame.");
+        }
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        device.destroy_semaphore(self.timeline, None);
+    }
+}
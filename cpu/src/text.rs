@@ -0,0 +1,657 @@
+use crate::{
+    frame::{FrameContext, FrameData},
+    pipeline::{create_graphics_pipeline, Blend, PassTarget, PipelineDesc},
+    sprite::{SpriteVertex, INDICES},
+    texture::{TextureId, Textures},
+    util::{DynBuffer, Gpu},
+};
+use ab_glyph::{point, Font, FontArc, GlyphId, InvalidFont, ScaleFont};
+use ash::{vk, Device};
+use glam::{vec2, Mat4, Vec2, Vec4};
+use std::collections::HashMap;
+
+const ATLAS_SIZE: u32 = 1024;
+/// Pixel size SDF glyphs are rasterized at, whatever size they end up drawn at.
+const SDF_SIZE: f32 = 48.0;
+/// How far, in pixels at `SDF_SIZE`, the distance field reaches outside of the glyph outline.
+const SDF_SPREAD: u32 = 6;
+
+/// Handle to a font loaded into the text renderer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FontId(u32);
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextStyle {
+    /// Height of the font in pixels.
+    pub size: f32,
+    pub color: Vec4,
+    /// Wraps lines at word boundaries once they would grow wider than this many pixels.
+    pub max_width: Option<f32>,
+    /// Multiplier for the font's line height.
+    pub line_spacing: f32,
+    /// Draw from a signed distance field, which stays sharp when scaled, instead of a bitmap
+    /// rasterized at exactly `size`.
+    pub sdf: bool,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle {
+            size: 16.0,
+            color: Vec4::ONE,
+            max_width: None,
+            line_spacing: 1.0,
+            sdf: false,
+        }
+    }
+}
+
+struct QueuedText {
+    font: FontId,
+    text: String,
+    pos: Vec2,
+    style: TextStyle,
+}
+
+/// Every font loaded so far. Glyphs are rasterized from these on demand.
+#[derive(Default)]
+pub struct Fonts {
+    fonts: Vec<FontArc>,
+}
+
+impl Fonts {
+    /// Loads a TrueType or OpenType font.
+    pub fn add(&mut self, data: Vec<u8>) -> Result<FontId, InvalidFont> {
+        self.fonts.push(FontArc::try_from_vec(data)?);
+        Ok(FontId(self.fonts.len() as u32 - 1))
+    }
+
+    /// Size of `text` once laid out with `style`, in pixels.
+    pub fn measure(&self, font: FontId, text: &str, style: &TextStyle) -> Vec2 {
+        layout(&self.fonts[font.0 as usize], text, style).1
+    }
+}
+
+/// Text queued for the current frame. Cleared every time the renderer draws it.
+#[derive(Default)]
+pub struct TextBatch {
+    texts: Vec<QueuedText>,
+}
+
+impl TextBatch {
+    /// Queues `text` with the top left corner of its first line at `pos`, in pixels.
+    pub fn draw(&mut self, font: FontId, text: impl Into<String>, pos: Vec2, style: TextStyle) {
+        self.texts.push(QueuedText {
+            font,
+            text: text.into(),
+            pos,
+            style,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.texts.clear();
+    }
+}
+
+/// Positions every glyph of `text` relative to the top left corner of the first line, applying
+/// kerning and wrapping lines at `style.max_width`. Returns the glyphs' baseline origins and the
+/// size of the laid out block, whose width leaves out whitespace at the end of lines.
+fn layout(font: &FontArc, text: &str, style: &TextStyle) -> (Vec<(GlyphId, Vec2)>, Vec2) {
+    let scaled = font.as_scaled(style.size);
+    let line_height = (scaled.ascent() - scaled.descent() + scaled.line_gap()) * style.line_spacing;
+    let max_width = style.max_width.unwrap_or(f32::INFINITY);
+    let mut glyphs: Vec<(GlyphId, Vec2)> = Vec::new();
+    // where each glyph ends on its line, `None` for whitespace
+    let mut ends: Vec<Option<f32>> = Vec::new();
+    let mut y = scaled.ascent();
+    for line in text.lines() {
+        let mut line_start = glyphs.len();
+        // first glyph after the most recent whitespace on the current line
+        let mut wrap_at: Option<usize> = None;
+        let mut prev = None;
+        let mut x: f32 = 0.0;
+        for c in line.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(prev) = prev {
+                x += scaled.kern(prev, id);
+            }
+            let advance = scaled.h_advance(id);
+            if !c.is_whitespace() && x + advance > max_width && glyphs.len() > line_start {
+                y += line_height;
+                match wrap_at.filter(|w| *w > line_start) {
+                    // move the current word onto the new line
+                    Some(w) => {
+                        let dx = glyphs.get(w).map_or(x, |g| g.1.x);
+                        for g in &mut glyphs[w..] {
+                            g.1 = vec2(g.1.x - dx, y);
+                        }
+                        for end in ends[w..].iter_mut().flatten() {
+                            *end -= dx;
+                        }
+                        x -= dx;
+                        line_start = w;
+                    }
+                    // a single word wider than the line has to be broken up
+                    None => {
+                        x = 0.0;
+                        line_start = glyphs.len();
+                    }
+                }
+                wrap_at = None;
+            }
+            glyphs.push((id, vec2(x, y)));
+            x += advance;
+            ends.push((!c.is_whitespace()).then_some(x));
+            prev = Some(id);
+            if c.is_whitespace() {
+                wrap_at = Some(glyphs.len());
+            }
+        }
+        y += line_height;
+    }
+    let width = ends.into_iter().flatten().fold(0.0, f32::max);
+    (glyphs, vec2(width, y - scaled.ascent()))
+}
+
+#[derive(Clone, Copy)]
+struct CachedGlyph {
+    uv: [Vec2; 2],
+    /// Top left corner relative to the baseline origin, in pixels at the rasterized size.
+    offset: Vec2,
+    size: Vec2,
+}
+
+/// Font, glyph and rasterized size in quarter pixels, or 0 for SDF glyphs.
+type GlyphKey = (FontId, GlyphId, u32);
+
+/// CPU copy of the glyph atlas texture, filled row by row from the top left.
+#[derive(Default)]
+struct GlyphAtlas {
+    pixels: Vec<u8>,
+    cursor: (u32, u32),
+    row_height: u32,
+    /// Region written since the last upload, as min and max corners.
+    dirty: Option<((u32, u32), (u32, u32))>,
+    /// `None` for glyphs without an outline, such as spaces.
+    glyphs: HashMap<GlyphKey, Option<CachedGlyph>>,
+    /// Set when a glyph did not fit, meaning the atlas has to be cleared and refilled.
+    full: bool,
+}
+
+impl GlyphAtlas {
+    fn reset(&mut self) {
+        self.pixels.clear();
+        self.pixels.resize((ATLAS_SIZE * ATLAS_SIZE) as usize, 0);
+        self.cursor = (0, 0);
+        self.row_height = 0;
+        self.dirty = Some(((0, 0), (ATLAS_SIZE, ATLAS_SIZE)));
+        self.glyphs.clear();
+        self.full = false;
+    }
+
+    /// Copies a `width` x `height` coverage bitmap into free space and returns its uv rectangle.
+    fn insert(&mut self, width: u32, height: u32, bitmap: &[u8]) -> Option<[Vec2; 2]> {
+        // leave a pixel between glyphs so linear filtering doesn't bleed
+        if self.cursor.0 + width + 1 > ATLAS_SIZE {
+            self.cursor = (0, self.cursor.1 + self.row_height + 1);
+            self.row_height = 0;
+        }
+        if self.cursor.1 + height + 1 > ATLAS_SIZE || width + 1 > ATLAS_SIZE {
+            self.full = true;
+            return None;
+        }
+        let (x, y) = self.cursor;
+        for row in 0..height {
+            let dst = ((y + row) * ATLAS_SIZE + x) as usize;
+            let src = (row * width) as usize;
+            self.pixels[dst..dst + width as usize]
+                .copy_from_slice(&bitmap[src..src + width as usize]);
+        }
+        self.cursor.0 += width + 1;
+        self.row_height = self.row_height.max(height);
+        let (min, max) = self.dirty.unwrap_or(((x, y), (x, y)));
+        self.dirty = Some((
+            (min.0.min(x), min.1.min(y)),
+            (max.0.max(x + width), max.1.max(y + height)),
+        ));
+        let size = ATLAS_SIZE as f32;
+        Some([
+            vec2(x as f32, y as f32) / size,
+            vec2((x + width) as f32, (y + height) as f32) / size,
+        ])
+    }
+
+    fn glyph(&mut self, fonts: &[FontArc], key: GlyphKey) -> Option<CachedGlyph> {
+        if let Some(cached) = self.glyphs.get(&key) {
+            return *cached;
+        }
+        let (font, id, size) = key;
+        let sdf = size == 0;
+        let px = if sdf { SDF_SIZE } else { size as f32 / 4.0 };
+        let outline =
+            fonts[font.0 as usize].outline_glyph(id.with_scale_and_position(px, point(0.0, 0.0)));
+        let cached = outline.and_then(|outline| {
+            let bounds = outline.px_bounds();
+            let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+            let pad = if sdf { SDF_SPREAD } else { 0 };
+            let (w, h) = (width + 2 * pad, height + 2 * pad);
+            let mut bitmap = vec![0u8; (w * h) as usize];
+            outline.draw(|x, y, c| {
+                bitmap[((y + pad) * w + x + pad) as usize] = (c.clamp(0.0, 1.0) * 255.0) as u8;
+            });
+            if sdf {
+                bitmap = distance_field(&bitmap, w, h);
+            }
+            let uv = self.insert(w, h, &bitmap)?;
+            Some(CachedGlyph {
+                uv,
+                offset: vec2(bounds.min.x, bounds.min.y) - pad as f32,
+                size: vec2(w as f32, h as f32),
+            })
+        });
+        if !self.full {
+            self.glyphs.insert(key, cached);
+        }
+        cached
+    }
+}
+
+/// Turns a coverage bitmap into a signed distance field: 0.5 on the outline, rising to 1 at
+/// `SDF_SPREAD` pixels inside and falling to 0 at `SDF_SPREAD` pixels outside.
+fn distance_field(coverage: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let spread = SDF_SPREAD as i32;
+    let inside = |x: i32, y: i32| {
+        x >= 0
+            && y >= 0
+            && x < width as i32
+            && y < height as i32
+            && coverage[(y as u32 * width + x as u32) as usize] >= 128
+    };
+    let mut field = vec![0u8; coverage.len()];
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let is_inside = inside(x, y);
+            // distance to the closest pixel on the other side of the outline
+            let mut nearest = spread as f32;
+            for dy in -spread..=spread {
+                for dx in -spread..=spread {
+                    if inside(x + dx, y + dy) != is_inside {
+                        nearest = nearest.min(((dx * dx + dy * dy) as f32).sqrt());
+                    }
+                }
+            }
+            let signed = if is_inside { nearest } else { -nearest };
+            let value = 0.5 + signed / (2.0 * spread as f32);
+            field[(y as u32 * width + x as u32) as usize] = (value.clamp(0.0, 1.0) * 255.0) as u8;
+        }
+    }
+    field
+}
+
+/// The vertex buffer each frame in flight writes its glyph quads to.
+struct TextFrame {
+    vert_buff: DynBuffer,
+}
+
+impl FrameData for TextFrame {
+    unsafe fn destroy(&mut self, device: &Device) {
+        self.vert_buff.destroy(device);
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct TextRenderer {
+    atlas: GlyphAtlas,
+    atlas_tex: TextureId,
+    pipeline_layout: vk::PipelineLayout,
+    /// Bitmap and SDF pipelines.
+    pipelines: [vk::Pipeline; 2],
+    vertices: Vec<SpriteVertex>,
+    /// Number of vertices drawn with each pipeline this frame.
+    counts: [u32; 2],
+}
+
+impl TextRenderer {
+    pub unsafe fn new(
+        gpu: Gpu,
+        shader_module: vk::ShaderModule,
+        target: PassTarget,
+        textures: &mut Textures,
+    ) -> Self {
+        let device = gpu.device;
+        let set_layouts = [textures.set_layout];
+        let push_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .size(size_of::<Mat4>() as u32)];
+        let layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_ranges);
+        let pipeline_layout = device
+            .create_pipeline_layout(&layout_info, None)
+            .expect("Failed to create text pipeline layout.");
+        let pipelines = [c"text_frag", c"text_sdf_frag"].map(|frag| {
+            create_graphics_pipeline(
+                device,
+                shader_module,
+                target,
+                pipeline_layout,
+                &PipelineDesc {
+                    name: "text",
+                    vert: c"sprite_vert",
+                    frag,
+                    bindings: &SpriteVertex::binding_descr(),
+                    attrs: &SpriteVertex::attr_descr(),
+                    topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                    cull_mode: vk::CullModeFlags::NONE,
+                    depth_test: false,
+                    depth_write: false,
+                    blend: Blend::Alpha,
+                },
+            )
+        });
+        let mut atlas = GlyphAtlas::default();
+        atlas.reset();
+        atlas.dirty = None;
+        TextRenderer {
+            atlas_tex: textures.create(
+                gpu,
+                ATLAS_SIZE,
+                ATLAS_SIZE,
+                vk::Format::R8_UNORM,
+                &atlas.pixels,
+                "glyph atlas",
+            ),
+            atlas,
+            pipeline_layout,
+            pipelines,
+            vertices: Vec::new(),
+            counts: [0; 2],
+        }
+    }
+
+    /// Gives each of `frames` the vertex buffer it writes its glyphs to.
+    pub unsafe fn attach_frames(gpu: Gpu, frames: &mut [FrameContext]) {
+        for frame in frames.iter_mut() {
+            frame.attach(TextFrame {
+                vert_buff: DynBuffer::new(
+                    gpu,
+                    4096 * size_of::<SpriteVertex>(),
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    "text vertices",
+                ),
+            });
+        }
+    }
+
+    /// Builds the quads for every queued text, rasterizing glyphs missing from the atlas. Records
+    /// its own upload, so it has to be called before the render pass begins.
+    pub unsafe fn prepare(
+        &mut self,
+        gpu: Gpu,
+        frame: &mut FrameContext,
+        textures: &Textures,
+        fonts: &Fonts,
+        batch: &mut TextBatch,
+    ) {
+        if !self.build(fonts, batch) {
+            // start over with an empty atlas, as glyphs from earlier this frame may be evicted
+            self.atlas.reset();
+            self.build(fonts, batch);
+        }
+        batch.texts.clear();
+        if let Some((min, max)) = self.atlas.dirty.take() {
+            let region = vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: min.0 as i32,
+                    y: min.1 as i32,
+                },
+                extent: vk::Extent2D {
+                    width: max.0 - min.0,
+                    height: max.1 - min.1,
+                },
+            };
+            let pixels: Vec<u8> = (min.1..max.1)
+                .flat_map(|y| {
+                    let row = (y * ATLAS_SIZE) as usize;
+                    self.atlas.pixels[row + min.0 as usize..row + max.0 as usize]
+                        .iter()
+                        .copied()
+                })
+                .collect();
+            textures.update(gpu, self.atlas_tex, region, &pixels);
+        }
+        if !self.vertices.is_empty() {
+            frame
+                .get_mut::<TextFrame>()
+                .vert_buff
+                .write(gpu, &self.vertices);
+        }
+    }
+
+    /// Returns false if the atlas ran out of space.
+    fn build(&mut self, fonts: &Fonts, batch: &TextBatch) -> bool {
+        self.vertices.clear();
+        self.counts = [0; 2];
+        // bitmap text first, so each pipeline draws one contiguous range
+        for sdf in [false, true] {
+            let start = self.vertices.len();
+            for text in batch.texts.iter().filter(|t| t.style.sdf == sdf) {
+                let font = &fonts.fonts[text.font.0 as usize];
+                let (glyphs, _) = layout(font, &text.text, &text.style);
+                let (size_key, scale) = if sdf {
+                    (0, text.style.size / SDF_SIZE)
+                } else {
+                    ((text.style.size * 4.0).round() as u32, 1.0)
+                };
+                for (id, origin) in glyphs {
+                    let Some(glyph) = self.atlas.glyph(&fonts.fonts, (text.font, id, size_key))
+                    else {
+                        continue;
+                    };
+                    // bitmaps are only crisp when they line up with the pixel grid
+                    let origin = if sdf {
+                        text.pos + origin
+                    } else {
+                        (text.pos + origin).round()
+                    };
+                    let min = origin + glyph.offset * scale;
+                    let max = min + glyph.size * scale;
+                    let corners = [
+                        (vec2(min.x, min.y), glyph.uv[0]),
+                        (vec2(max.x, min.y), vec2(glyph.uv[1].x, glyph.uv[0].y)),
+                        (vec2(max.x, max.y), glyph.uv[1]),
+                        (vec2(min.x, max.y), vec2(glyph.uv[0].x, glyph.uv[1].y)),
+                    ];
+                    self.vertices.extend(INDICES.map(|i| SpriteVertex {
+                        pos: corners[i as usize].0,
+                        uv: corners[i as usize].1,
+                        col: text.style.color,
+                    }));
+                }
+            }
+            self.counts[sdf as usize] = (self.vertices.len() - start) as u32;
+        }
+        !self.atlas.full
+    }
+
+    /// Draws the quads built by `prepare`. Must be called inside the render pass.
+    pub unsafe fn draw(
+        &self,
+        device: &Device,
+        frame: &FrameContext,
+        textures: &Textures,
+        extent: vk::Extent2D,
+    ) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        let cmd = frame.cmd;
+        let proj = Mat4::orthographic_rh(
+            0.0,
+            extent.width as f32,
+            0.0,
+            extent.height as f32,
+            -1.0,
+            1.0,
+        );
+        device.cmd_bind_vertex_buffers(cmd, 0, &[frame.get::<TextFrame>().vert_buff.buff], &[0]);
+        let mut first = 0;
+        for (pipeline, count) in self.pipelines.into_iter().zip(self.counts) {
+            if count == 0 {
+                continue;
+            }
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                bytemuck::cast_slice(&proj.to_cols_array()),
+            );
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[textures.get(self.atlas_tex).set],
+                &[],
+            );
+            device.cmd_draw(cmd, count, 1, first, 0);
+            first += count;
+        }
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        for pipeline in self.pipelines {
+            device.destroy_pipeline(pipeline, None);
+        }
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A monospace font, so every glyph advances the same and nothing is kerned.
+    fn mono() -> FontArc {
+        let fonts = egui::FontDefinitions::default();
+        FontArc::try_from_vec(fonts.font_data["Hack"].font.to_vec()).unwrap()
+    }
+
+    fn style(max_width: Option<f32>) -> TextStyle {
+        TextStyle {
+            max_width,
+            ..Default::default()
+        }
+    }
+
+    /// The advance of every glyph and the height of every line.
+    fn metrics(font: &FontArc) -> (f32, f32) {
+        let scaled = font.as_scaled(style(None).size);
+        let advance = scaled.h_advance(scaled.glyph_id('a'));
+        (
+            advance,
+            scaled.ascent() - scaled.descent() + scaled.line_gap(),
+        )
+    }
+
+    /// Each glyph's position in glyph advances and lines.
+    fn cells(font: &FontArc, glyphs: &[(GlyphId, Vec2)]) -> Vec<(u32, u32)> {
+        let (advance, line_height) = metrics(font);
+        let ascent = font.as_scaled(style(None).size).ascent();
+        glyphs
+            .iter()
+            .map(|(_, pos)| {
+                let col = pos.x / advance;
+                let row = (pos.y - ascent) / line_height;
+                (col.round() as u32, row.round() as u32)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wraps_words_at_the_width_limit() {
+        let font = mono();
+        let (advance, line_height) = metrics(&font);
+        let (glyphs, size) = layout(&font, "aaaa bbbb cccc", &style(Some(advance * 10.5)));
+        let cells = cells(&font, &glyphs);
+        // "aaaa bbbb " fits, the first c doesn't, so the whole word moves down
+        assert_eq!(cells[5], (5, 0));
+        assert_eq!(&cells[10..], [(0, 1), (1, 1), (2, 1), (3, 1)]);
+        // the space the line was broken at isn't part of its width
+        assert!(size.abs_diff_eq(vec2(advance * 9.0, line_height * 2.0), 1e-3));
+    }
+
+    #[test]
+    fn breaks_words_wider_than_the_line() {
+        let font = mono();
+        let (advance, _) = metrics(&font);
+        let (glyphs, size) = layout(&font, "aaaaaaaaaaaa", &style(Some(advance * 5.5)));
+        let rows: Vec<_> = cells(&font, &glyphs).iter().map(|c| c.1).collect();
+        assert_eq!(rows, [0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 2, 2]);
+        assert!((size.x - advance * 5.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn starts_a_line_at_every_newline() {
+        let font = mono();
+        let (advance, line_height) = metrics(&font);
+        let (glyphs, size) = layout(&font, "ab\n\ncde", &style(None));
+        assert_eq!(
+            cells(&font, &glyphs),
+            [(0, 0), (1, 0), (0, 2), (1, 2), (2, 2)]
+        );
+        assert!(size.abs_diff_eq(vec2(advance * 3.0, line_height * 3.0), 1e-3));
+    }
+
+    #[test]
+    fn trailing_whitespace_has_no_width() {
+        let font = mono();
+        let (advance, _) = metrics(&font);
+        let (_, size) = layout(&font, "ab   \ncd ", &style(None));
+        assert!((size.x - advance * 2.0).abs() < 1e-3);
+    }
+
+    /// A 32x32 coverage bitmap with a filled square from 10 to 21 on both axes.
+    fn square() -> Vec<u8> {
+        let inside = 10..22;
+        (0..32 * 32)
+            .map(|i| {
+                let (x, y) = (i % 32, i / 32);
+                if inside.contains(&x) && inside.contains(&y) {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn distance_field_is_signed_around_the_outline() {
+        let field = distance_field(&square(), 32, 32);
+        let at = |x: usize, y: usize| field[y * 32 + x];
+        // one pixel on either side of the left edge, along the middle row
+        assert!(at(10, 16) > 128 && at(9, 16) < 128);
+        // as far above the middle as below it, give or take rounding
+        assert!((at(10, 16) - 128).abs_diff(127 - at(9, 16)) <= 1);
+        // rising steadily from the spread outside to the spread inside
+        let row: Vec<_> = (4..=15).map(|x| at(x, 16)).collect();
+        assert!(row.windows(2).all(|w| w[0] < w[1]), "{row:?}");
+    }
+
+    #[test]
+    fn distance_field_saturates_at_the_spread() {
+        let field = distance_field(&square(), 32, 32);
+        let at = |x: usize, y: usize| field[y * 32 + x];
+        let spread = SDF_SPREAD as usize;
+        // the center is further than the spread from the outline, as is the corner
+        assert_eq!(at(16, 16), 255);
+        assert_eq!(at(0, 0), 0);
+        // exactly the spread outside of the left edge, and one pixel closer
+        assert_eq!(at(10 - spread, 16), 0);
+        assert!(at(10 - spread + 1, 16) > 0);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::util::{make_image, Gpu};
+use ash::{vk, Device};
+
+const MAX_TEXTURES: u32 = 256;
+
+/// Handle to a texture owned by the renderer, from `Context::create_texture`. Defaults to `WHITE`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextureId(pub(crate) u32);
+
+impl TextureId {
+    /// A 1x1 opaque white texture, for untextured quads.
+    pub const WHITE: TextureId = TextureId(0);
+}
+
+pub(crate) struct Texture {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+    pub set: vk::DescriptorSet,
+    pub extent: vk::Extent2D,
+}
+
+/// Owns every sampled texture together with the descriptor set layout they are bound through:
+/// binding 0 is the image, binding 1 the sampler.
+#[derive(Default)]
+pub(crate) struct Textures {
+    pub set_layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sampler: vk::Sampler,
+    /// Freed slots are `None` and get reused by the next texture created.
+    textures: Vec<Option<Texture>>,
+    /// Textures the game freed, destroyed once the numbered frame that last used them is done.
+    retired: Vec<(u64, TextureId)>,
+}
+
+impl Textures {
+    pub unsafe fn new(gpu: Gpu) -> Self {
+        let device = gpu.device;
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+        ];
+        let set_layout = device
+            .create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings),
+                None,
+            )
+            .expect("Failed to create texture descriptor set layout.");
+        let pool_sizes = [
+            vk::DescriptorPoolSize::default()
+                .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(MAX_TEXTURES),
+            vk::DescriptorPoolSize::default()
+                .ty(vk::DescriptorType::SAMPLER)
+                .descriptor_count(MAX_TEXTURES),
+        ];
+        let pool = device
+            .create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+                    .max_sets(MAX_TEXTURES)
+                    .pool_sizes(&pool_sizes),
+                None,
+            )
+            .expect("Failed to create texture descriptor pool.");
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        let sampler = device
+            .create_sampler(&sampler_info, None)
+            .expect("Failed to create sampler.");
+        let mut textures = Textures {
+            set_layout,
+            pool,
+            sampler,
+            textures: Vec::new(),
+            retired: Vec::new(),
+        };
+        textures.create(gpu, 1, 1, vk::Format::R8G8B8A8_UNORM, &[255; 4], "white");
+        textures
+    }
+
+    /// Uploads `pixels` (tightly packed, in `format`) into a new device local texture.
+    pub unsafe fn create(
+        &mut self,
+        gpu: Gpu,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        pixels: &[u8],
+        name: &str,
+    ) -> TextureId {
+        let device = gpu.device;
+        let (image, memory, view) = make_image(
+            device,
+            gpu.mem_props,
+            vk::Extent2D { width, height },
+            format,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            vk::ImageAspectFlags::COLOR,
+            name,
+        );
+        let set_layouts = [self.set_layout];
+        let set = device
+            .allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(self.pool)
+                    .set_layouts(&set_layouts),
+            )
+            .expect("Failed to allocate texture descriptor set.")[0];
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let sampler_info = [vk::DescriptorImageInfo::default().sampler(self.sampler)];
+        let writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image_info),
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler_info),
+        ];
+        device.update_descriptor_sets(&writes, &[]);
+        let texture = Some(Texture {
+            image,
+            memory,
+            view,
+            set,
+            extent: vk::Extent2D { width, height },
+        });
+        let id = match self.textures.iter().position(Option::is_none) {
+            Some(free) => {
+                self.textures[free] = texture;
+                TextureId(free as u32)
+            }
+            None => {
+                self.textures.push(texture);
+                TextureId(self.textures.len() as u32 - 1)
+            }
+        };
+        self.write(
+            gpu,
+            id,
+            vk::ImageLayout::UNDEFINED,
+            vk::Rect2D::default().extent(vk::Extent2D { width, height }),
+            pixels,
+        );
+        id
+    }
+
+    /// Overwrites `region` of an existing texture with `pixels`, which are tightly packed.
+    pub unsafe fn update(&self, gpu: Gpu, id: TextureId, region: vk::Rect2D, pixels: &[u8]) {
+        self.write(
+            gpu,
+            id,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            region,
+            pixels,
+        );
+    }
+
+    /// New textures are uploaded on the transfer queue and handed over to the graphics queue.
+    /// Updates stay on the graphics queue, which owns the texture and may still be sampling it.
+    unsafe fn write(
+        &self,
+        gpu: Gpu,
+        id: TextureId,
+        old_layout: vk::ImageLayout,
+        region: vk::Rect2D,
+        pixels: &[u8],
+    ) {
+        let image = self.get(id).image;
+        let device = gpu.device;
+        let staging = gpu.make_staging(pixels);
+        let barrier = |old, new, src_access, dst_access| {
+            [vk::ImageMemoryBarrier::default()
+                .old_layout(old)
+                .new_layout(new)
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(color_range())]
+        };
+        let copy = |cmd| {
+            // an update has to wait for earlier frames to finish sampling the texture
+            let src_stage = if old_layout == vk::ImageLayout::UNDEFINED {
+                vk::PipelineStageFlags::TOP_OF_PIPE
+            } else {
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+            };
+            device.cmd_pipeline_barrier(
+                cmd,
+                src_stage,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barrier(
+                    old_layout,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+            );
+            let copy = [vk::BufferImageCopy::default()
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .layer_count(1),
+                )
+                .image_offset(vk::Offset3D {
+                    x: region.offset.x,
+                    y: region.offset.y,
+                    z: 0,
+                })
+                .image_extent(vk::Extent3D {
+                    width: region.extent.width,
+                    height: region.extent.height,
+                    depth: 1,
+                })];
+            device.cmd_copy_buffer_to_image(
+                cmd,
+                staging.0,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &copy,
+            );
+        };
+        if old_layout == vk::ImageLayout::UNDEFINED {
+            gpu.upload(&[], &[image], copy);
+        } else {
+            gpu.one_time_submit(|cmd| {
+                copy(cmd);
+                device.cmd_pipeline_barrier(
+                    cmd,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &barrier(
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                );
+            });
+        }
+        device.destroy_buffer(staging.0, None);
+        device.free_memory(staging.1, None);
+    }
+
+    pub fn get(&self, id: TextureId) -> &Texture {
+        self.textures[id.0 as usize]
+            .as_ref()
+            .expect("Texture was already freed.")
+    }
+
+    /// Destroys a texture. The caller has to make sure no frame in flight still samples it.
+    pub unsafe fn free(&mut self, device: &Device, id: TextureId) {
+        if let Some(tex) = self.textures[id.0 as usize].take() {
+            device.destroy_image_view(tex.view, None);
+            device.destroy_image(tex.image, None);
+            device.free_memory(tex.memory, None);
+            device
+                .free_descriptor_sets(self.pool, &[tex.set])
+                .expect("Failed to free texture descriptor set.");
+        }
+    }
+
+    /// Frees `id` once frames up to `last_used` are done, see `free_retired`.
+    pub fn retire(&mut self, id: TextureId, last_used: u64) {
+        self.retired.push((last_used, id));
+    }
+
+    /// Destroys the retired textures that no frame up to `completed` uses anymore.
+    pub unsafe fn free_retired(&mut self, device: &Device, completed: u64) {
+        let mut retired = std::mem::take(&mut self.retired);
+        retired.retain(|&(last_used, id)| {
+            let done = last_used <= completed;
+            if done {
+                self.free(device, id);
+            }
+            !done
+        });
+        self.retired = retired;
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        for tex in self.textures.drain(..).flatten() {
+            device.destroy_image_view(tex.view, None);
+            device.destroy_image(tex.image, None);
+            device.free_memory(tex.memory, None);
+        }
+        device.destroy_sampler(self.sampler, None);
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
+
+pub(crate) fn color_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::default()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+}
@@ -0,0 +1,109 @@
+//! Frame timing: variable delta time for rendering, a fixed timestep for simulation, and pacing
+//! of when the next frame is requested.
+use std::time::{Duration, Instant};
+
+/// Fixed steps run per frame at most. After a long stall (a breakpoint, dragging the window) the
+/// remaining time is dropped instead of being simulated all at once.
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedrawMode {
+    /// Render frames back to back, limited only by the FPS cap and the present mode.
+    Continuous,
+    /// Only render after input or an explicit redraw request, for tools that sit idle.
+    OnDemand,
+}
+
+pub struct Clock {
+    pub redraw_mode: RedrawMode,
+    fps_cap: Option<f32>,
+    fixed_dt: f32,
+    accumulator: f32,
+    dt: f32,
+    elapsed: f64,
+    frame_count: u64,
+    last_frame: Option<Instant>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock {
+            redraw_mode: RedrawMode::Continuous,
+            fps_cap: None,
+            fixed_dt: 1.0 / 60.0,
+            accumulator: 0.0,
+            dt: 0.0,
+            elapsed: 0.0,
+            frame_count: 0,
+            last_frame: None,
+        }
+    }
+}
+
+impl Clock {
+    /// Starts a new frame and returns how many fixed steps to simulate before rendering it.
+    pub(crate) fn tick(&mut self) -> u32 {
+        let now = Instant::now();
+        self.dt = self
+            .last_frame
+            .replace(now)
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        self.elapsed += self.dt as f64;
+        self.frame_count += 1;
+        self.accumulator += self.dt;
+        let steps = (self.accumulator / self.fixed_dt) as u32;
+        self.accumulator -= steps as f32 * self.fixed_dt;
+        if steps > MAX_STEPS_PER_FRAME {
+            self.accumulator = 0.0;
+            return MAX_STEPS_PER_FRAME;
+        }
+        steps
+    }
+
+    /// When the next frame may start, if the FPS cap says it has to wait.
+    pub(crate) fn next_frame(&self) -> Option<Instant> {
+        let cap = self.fps_cap?;
+        let last = self.last_frame?;
+        Some(last + Duration::from_secs_f32(1.0 / cap))
+    }
+
+    /// Seconds since the previous frame.
+    pub fn dt(&self) -> f32 {
+        self.dt
+    }
+
+    /// Seconds since the first frame.
+    pub fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    /// Frames started so far, including the current one.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn fixed_dt(&self) -> f32 {
+        self.fixed_dt
+    }
+
+    pub fn set_fixed_dt(&mut self, fixed_dt: f32) {
+        assert!(fixed_dt > 0.0, "Fixed timestep must be positive.");
+        self.fixed_dt = fixed_dt;
+    }
+
+    /// How far rendering is between the last fixed step and the next one, from 0 to 1. Render
+    /// `prev.lerp(current, alpha)` for motion that is smooth at any frame rate.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.fixed_dt
+    }
+
+    pub fn fps_cap(&self) -> Option<f32> {
+        self.fps_cap
+    }
+
+    /// Limits continuous rendering to `fps` frames per second. `None` removes the limit.
+    pub fn set_fps_cap(&mut self, fps: Option<f32>) {
+        assert!(fps.is_none_or(|fps| fps > 0.0), "FPS cap must be positive.");
+        self.fps_cap = fps;
+    }
+}
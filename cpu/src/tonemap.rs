@@ -0,0 +1,248 @@
+//! The scene is rendered to a floating point target, which the output pass tone maps and encodes
+//! for the swapchain's format and color space.
+use crate::pipeline::{create_graphics_pipeline, Blend, PassTarget, PipelineDesc};
+use ash::{vk, Device};
+
+pub(crate) const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Brightness of 1.0 in the scene, and of the UI, on HDR displays.
+const PAPER_WHITE_NITS: f32 = 200.0;
+/// Peak brightness HDR output is clamped to.
+const MAX_NITS: f32 = 1000.0;
+
+/// How the final image is encoded for the display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputMode {
+    /// 8 bit sRGB, tone mapped. Always available.
+    Sdr,
+    /// 10 bit sRGB, tone mapped, for less banding.
+    Sdr10Bit,
+    /// Extended sRGB linear (scRGB) in half floats, where 1.0 is 80 nits.
+    ExtendedSrgbLinear,
+    /// HDR10: Rec.2020 primaries with the ST 2084 (PQ) curve.
+    Hdr10,
+}
+
+impl OutputMode {
+    pub(crate) const ALL: [OutputMode; 4] = [
+        OutputMode::Sdr,
+        OutputMode::Sdr10Bit,
+        OutputMode::ExtendedSrgbLinear,
+        OutputMode::Hdr10,
+    ];
+
+    /// The surface format to use for this mode, if the surface supports one.
+    pub(crate) fn pick_format(
+        self,
+        formats: &[vk::SurfaceFormatKHR],
+    ) -> Option<vk::SurfaceFormatKHR> {
+        use vk::{ColorSpaceKHR as cs, Format as f};
+        let ten_bit = [f::A2B10G10R10_UNORM_PACK32, f::A2R10G10B10_UNORM_PACK32];
+        let (candidates, color_space): (&[vk::Format], _) = match self {
+            OutputMode::Sdr => (&[f::B8G8R8A8_SRGB, f::R8G8B8A8_SRGB], cs::SRGB_NONLINEAR),
+            OutputMode::Sdr10Bit => (&ten_bit, cs::SRGB_NONLINEAR),
+            OutputMode::ExtendedSrgbLinear => {
+                (&[f::R16G16B16A16_SFLOAT], cs::EXTENDED_SRGB_LINEAR_EXT)
+            }
+            OutputMode::Hdr10 => (&ten_bit, cs::HDR10_ST2084_EXT),
+        };
+        formats
+            .iter()
+            .find(|f| f.color_space == color_space && candidates.contains(&f.format))
+            .copied()
+    }
+}
+
+/// The output encoding and paper white brightness, as the shaders' `encode_output` takes them.
+pub(crate) fn output_encoding(format: vk::SurfaceFormatKHR) -> [f32; 2] {
+    let transfer = match (format.color_space, format.format) {
+        (vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT, _) => 2.0,
+        (vk::ColorSpaceKHR::HDR10_ST2084_EXT, _) => 3.0,
+        // sRGB formats are encoded by the hardware when written
+        (
+            _,
+            vk::Format::B8G8R8A8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32,
+        ) => 0.0,
+        _ => 1.0,
+    };
+    [transfer, PAPER_WHITE_NITS]
+}
+
+#[derive(Default)]
+pub(crate) struct Tonemapper {
+    set_layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    sampler: vk::Sampler,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    pub exposure: f32,
+}
+
+impl Tonemapper {
+    pub unsafe fn new(
+        device: &Device,
+        shader_module: vk::ShaderModule,
+        target: PassTarget,
+    ) -> Self {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+        ];
+        let set_layout = device
+            .create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings),
+                None,
+            )
+            .expect("Failed to create tonemap descriptor set layout.");
+        let pool_sizes = [
+            vk::DescriptorPoolSize::default()
+                .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1),
+            vk::DescriptorPoolSize::default()
+                .ty(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1),
+        ];
+        let pool = device
+            .create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(1)
+                    .pool_sizes(&pool_sizes),
+                None,
+            )
+            .expect("Failed to create tonemap descriptor pool.");
+        let set_layouts = [set_layout];
+        let set = device
+            .allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(pool)
+                    .set_layouts(&set_layouts),
+            )
+            .expect("Failed to allocate tonemap descriptor set.")[0];
+        // the scene target matches the swapchain's size, so there's nothing to filter
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        let sampler = device
+            .create_sampler(&sampler_info, None)
+            .expect("Failed to create tonemap sampler.");
+        let push_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .size(4 * size_of::<f32>() as u32)];
+        let layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_ranges);
+        let pipeline_layout = device
+            .create_pipeline_layout(&layout_info, None)
+            .expect("Failed to create tonemap pipeline layout.");
+        let mut tonemapper = Tonemapper {
+            set_layout,
+            pool,
+            set,
+            sampler,
+            pipeline_layout,
+            pipeline: vk::Pipeline::null(),
+            exposure: 1.0,
+        };
+        tonemapper.create_pipeline(device, shader_module, target);
+        tonemapper
+    }
+
+    /// (Re)creates the pipeline, which is needed whenever the output format changes.
+    pub unsafe fn create_pipeline(
+        &mut self,
+        device: &Device,
+        shader_module: vk::ShaderModule,
+        target: PassTarget,
+    ) {
+        device.destroy_pipeline(self.pipeline, None);
+        self.pipeline = create_graphics_pipeline(
+            device,
+            shader_module,
+            target,
+            self.pipeline_layout,
+            &PipelineDesc {
+                name: "tone map",
+                vert: c"fullscreen_vert",
+                frag: c"tonemap_frag",
+                bindings: &[],
+                attrs: &[],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_test: false,
+                depth_write: false,
+                // the output is opaque, so this just overwrites
+                blend: Blend::Alpha,
+            },
+        );
+    }
+
+    /// Points the tone mapper at a new scene target. The previous one must no longer be in use.
+    pub unsafe fn set_input(&self, device: &Device, view: vk::ImageView) {
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let sampler_info = [vk::DescriptorImageInfo::default().sampler(self.sampler)];
+        let writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_set(self.set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image_info),
+            vk::WriteDescriptorSet::default()
+                .dst_set(self.set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler_info),
+        ];
+        device.update_descriptor_sets(&writes, &[]);
+    }
+
+    /// Draws the tone mapped scene over the whole output. Must be called inside the output pass.
+    pub unsafe fn draw(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        format: vk::SurfaceFormatKHR,
+    ) {
+        let [transfer, paper_white] = output_encoding(format);
+        let params = [transfer, paper_white, self.exposure, MAX_NITS];
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[self.set],
+            &[],
+        );
+        device.cmd_push_constants(
+            cmd,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            bytemuck::cast_slice(&params),
+        );
+        device.cmd_draw(cmd, 3, 1, 0, 0);
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        device.destroy_sampler(self.sampler, None);
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
@@ -0,0 +1,343 @@
+//! Renders egui output. egui works in points, which are scaled to physical pixels by
+//! `pixels_per_point`; vertex colors and textures are sRGB with premultiplied alpha.
+use crate::{
+    frame::{FrameContext, FrameData},
+    pipeline::{create_graphics_pipeline, Blend, PassTarget, PipelineDesc},
+    texture::{TextureId, Textures},
+    tonemap::output_encoding,
+    util::{DynBuffer, Gpu},
+};
+use ash::{vk, Device};
+use egui::{epaint, ClippedPrimitive, ImageData, TexturesDelta};
+use std::collections::HashMap;
+
+fn binding_descr() -> Vec<vk::VertexInputBindingDescription> {
+    vec![vk::VertexInputBindingDescription::default()
+        .stride(size_of::<epaint::Vertex>() as u32)
+        .binding(0)
+        .input_rate(vk::VertexInputRate::VERTEX)]
+}
+
+fn attr_descr() -> Vec<vk::VertexInputAttributeDescription> {
+    vec![
+        vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(core::mem::offset_of!(epaint::Vertex, pos) as u32),
+        vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(core::mem::offset_of!(epaint::Vertex, uv) as u32),
+        vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R8G8B8A8_UNORM)
+            .offset(core::mem::offset_of!(epaint::Vertex, color) as u32),
+    ]
+}
+
+/// A mesh's range in this frame's vertex / index buffers.
+struct DrawCall {
+    texture: TextureId,
+    clip_rect: egui::Rect,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+}
+
+/// The buffers each frame in flight writes its meshes to.
+struct EguiFrame {
+    vert_buff: DynBuffer,
+    ind_buff: DynBuffer,
+}
+
+impl FrameData for EguiFrame {
+    unsafe fn destroy(&mut self, device: &Device) {
+        self.vert_buff.destroy(device);
+        self.ind_buff.destroy(device);
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct EguiRenderer {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    textures: HashMap<egui::TextureId, TextureId>,
+    /// Textures egui is done with, destroyed once the numbered frame that last used them is done.
+    to_free: Vec<(u64, TextureId)>,
+    draws: Vec<DrawCall>,
+    pixels_per_point: f32,
+}
+
+impl EguiRenderer {
+    pub unsafe fn new(
+        gpu: Gpu,
+        shader_module: vk::ShaderModule,
+        target: PassTarget,
+        textures: &Textures,
+        frames: &mut [FrameContext],
+    ) -> Self {
+        let device = gpu.device;
+        let set_layouts = [textures.set_layout];
+        // screen size in points, then the output encoding
+        let push_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .size(4 * size_of::<f32>() as u32)];
+        let layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_ranges);
+        let pipeline_layout = device
+            .create_pipeline_layout(&layout_info, None)
+            .expect("Failed to create egui pipeline layout.");
+        for frame in frames.iter_mut() {
+            frame.attach(EguiFrame {
+                vert_buff: DynBuffer::new(
+                    gpu,
+                    1 << 16,
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    "egui vertices",
+                ),
+                ind_buff: DynBuffer::new(
+                    gpu,
+                    1 << 16,
+                    vk::BufferUsageFlags::INDEX_BUFFER,
+                    "egui indices",
+                ),
+            });
+        }
+        let mut renderer = EguiRenderer {
+            pipeline_layout,
+            pixels_per_point: 1.0,
+            ..Default::default()
+        };
+        renderer.create_pipeline(device, shader_module, target);
+        renderer
+    }
+
+    /// (Re)creates the pipeline, which is needed whenever the output format changes.
+    pub unsafe fn create_pipeline(
+        &mut self,
+        device: &Device,
+        shader_module: vk::ShaderModule,
+        target: PassTarget,
+    ) {
+        device.destroy_pipeline(self.pipeline, None);
+        self.pipeline = create_graphics_pipeline(
+            device,
+            shader_module,
+            target,
+            self.pipeline_layout,
+            &PipelineDesc {
+                name: "egui",
+                vert: c"egui_vert",
+                frag: c"egui_frag",
+                bindings: &binding_descr(),
+                attrs: &attr_descr(),
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_test: false,
+                depth_write: false,
+                blend: Blend::Premultiplied,
+            },
+        );
+    }
+
+    /// Applies egui's texture changes for `this_frame`, the number of the next frame to be
+    /// submitted, and destroys the textures freed by frames up to `completed`.
+    pub unsafe fn update_textures(
+        &mut self,
+        gpu: Gpu,
+        textures: &mut Textures,
+        delta: TexturesDelta,
+        this_frame: u64,
+        completed: u64,
+    ) {
+        self.to_free.retain(|&(last_used, id)| {
+            let done = last_used <= completed;
+            if done {
+                textures.free(gpu.device, id);
+            }
+            !done
+        });
+        for (egui_id, image_delta) in delta.set {
+            let [width, height] = image_delta.image.size();
+            let pixels: Vec<u8> = match &image_delta.image {
+                ImageData::Color(image) => image.pixels.iter().flat_map(|c| c.to_array()).collect(),
+                ImageData::Font(image) => image
+                    .srgba_pixels(None)
+                    .flat_map(|c| c.to_array())
+                    .collect(),
+            };
+            match (image_delta.pos, self.textures.get(&egui_id)) {
+                (Some([x, y]), Some(&id)) => {
+                    let region = vk::Rect2D {
+                        offset: vk::Offset2D {
+                            x: x as i32,
+                            y: y as i32,
+                        },
+                        extent: vk::Extent2D {
+                            width: width as u32,
+                            height: height as u32,
+                        },
+                    };
+                    textures.update(gpu, id, region, &pixels);
+                }
+                (pos, old) => {
+                    assert!(pos.is_none(), "egui updated a texture it never created.");
+                    // the old texture may still be in use by frames in flight
+                    if let Some(&old) = old {
+                        self.to_free.push((this_frame, old));
+                    }
+                    let id = textures.create(
+                        gpu,
+                        width as u32,
+                        height as u32,
+                        vk::Format::R8G8B8A8_SRGB,
+                        &pixels,
+                        &format!("egui texture {egui_id:?}"),
+                    );
+                    self.textures.insert(egui_id, id);
+                }
+            }
+        }
+        for egui_id in delta.free {
+            if let Some(id) = self.textures.remove(&egui_id) {
+                self.to_free.push((this_frame, id));
+            }
+        }
+    }
+
+    /// Uploads the meshes for `frame`. Must be called after waiting for the slot's previous frame
+    /// and before its render pass begins.
+    pub unsafe fn prepare(
+        &mut self,
+        gpu: Gpu,
+        frame: &mut FrameContext,
+        primitives: Vec<ClippedPrimitive>,
+        pixels_per_point: f32,
+    ) {
+        self.pixels_per_point = pixels_per_point;
+        self.draws.clear();
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in primitives
+        {
+            // paint callbacks need a backend specific API, which we don't offer
+            let epaint::Primitive::Mesh(mesh) = primitive else {
+                continue;
+            };
+            let Some(&texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+            self.draws.push(DrawCall {
+                texture,
+                clip_rect,
+                first_index: indices.len() as u32,
+                index_count: mesh.indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+            });
+            vertices.extend(mesh.vertices);
+            indices.extend(mesh.indices);
+        }
+        if !self.draws.is_empty() {
+            let buffs = frame.get_mut::<EguiFrame>();
+            buffs.vert_buff.write(gpu, &vertices);
+            buffs.ind_buff.write(gpu, &indices);
+        }
+    }
+
+    /// Draws the meshes uploaded by `prepare`. Must be called inside the output render pass.
+    pub unsafe fn draw(
+        &self,
+        device: &Device,
+        frame: &FrameContext,
+        textures: &Textures,
+        extent: vk::Extent2D,
+        format: vk::SurfaceFormatKHR,
+    ) {
+        if self.draws.is_empty() {
+            return;
+        }
+        let cmd = frame.cmd;
+        let buffs = frame.get::<EguiFrame>();
+        let ppp = self.pixels_per_point;
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_vertex_buffers(cmd, 0, &[buffs.vert_buff.buff], &[0]);
+        device.cmd_bind_index_buffer(cmd, buffs.ind_buff.buff, 0, vk::IndexType::UINT32);
+        let viewport = [vk::Viewport::default()
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .max_depth(1.0)];
+        device.cmd_set_viewport(cmd, 0, &viewport);
+        let [transfer, paper_white] = output_encoding(format);
+        let params = [
+            extent.width as f32 / ppp,
+            extent.height as f32 / ppp,
+            transfer,
+            paper_white,
+        ];
+        device.cmd_push_constants(
+            cmd,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            0,
+            bytemuck::cast_slice(&params),
+        );
+        for draw in &self.draws {
+            // clip rects are in points, the scissor in pixels clamped to the framebuffer
+            let min_x = (draw.clip_rect.min.x * ppp)
+                .round()
+                .clamp(0.0, extent.width as f32);
+            let min_y = (draw.clip_rect.min.y * ppp)
+                .round()
+                .clamp(0.0, extent.height as f32);
+            let max_x = (draw.clip_rect.max.x * ppp)
+                .round()
+                .clamp(min_x, extent.width as f32);
+            let max_y = (draw.clip_rect.max.y * ppp)
+                .round()
+                .clamp(min_y, extent.height as f32);
+            if max_x == min_x || max_y == min_y {
+                continue;
+            }
+            let scissor = [vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: min_x as i32,
+                    y: min_y as i32,
+                },
+                extent: vk::Extent2D {
+                    width: (max_x - min_x) as u32,
+                    height: (max_y - min_y) as u32,
+                },
+            }];
+            device.cmd_set_scissor(cmd, 0, &scissor);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[textures.get(draw.texture).set],
+                &[],
+            );
+            device.cmd_draw_indexed(
+                cmd,
+                draw.index_count,
+                1,
+                draw.first_index,
+                draw.vertex_offset,
+                0,
+            );
+        }
+    }
+
+    /// The textures themselves are owned, and destroyed, by `Textures`.
+    pub unsafe fn destroy(&self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+    }
+}
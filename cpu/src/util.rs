@@ -0,0 +1,395 @@
+use crate::{device::Queues, label::name_object, texture::color_range};
+use ash::{util::read_spv, vk, Device};
+use std::io::Cursor;
+use tracing::debug_span;
+
+/// Everything needed to create resources and upload to them outside of a frame.
+#[derive(Clone, Copy)]
+pub(crate) struct Gpu<'a> {
+    pub device: &'a Device,
+    pub mem_props: &'a vk::PhysicalDeviceMemoryProperties,
+    /// For the graphics queue.
+    pub command_pool: vk::CommandPool,
+    /// For the transfer queue.
+    pub transfer_pool: vk::CommandPool,
+    pub queues: Queues,
+}
+
+impl Gpu<'_> {
+    pub unsafe fn make_buffer(
+        &self,
+        size: u64,
+        props: vk::MemoryPropertyFlags,
+        usage: vk::BufferUsageFlags,
+        name: &str,
+    ) -> (vk::Buffer, vk::DeviceMemory) {
+        make_buffer(self.device, self.mem_props, size, props, usage, name)
+    }
+
+    pub unsafe fn make_staging<T: Copy>(&self, data: &[T]) -> (vk::Buffer, vk::DeviceMemory) {
+        make_staging(self.device, self.mem_props, data)
+    }
+
+    /// Submits to the graphics queue.
+    pub unsafe fn one_time_submit(&self, record: impl FnOnce(vk::CommandBuffer)) {
+        one_time_submit(self.device, self.command_pool, self.queues.graphics, record)
+    }
+
+    /// Records `record`, which writes `buffers` and `images`, on the transfer queue, then makes
+    /// them available to the graphics queue. `record` must leave the images in
+    /// `TRANSFER_DST_OPTIMAL`; they end up in `SHADER_READ_ONLY_OPTIMAL`. If the transfer queue is
+    /// from another family their ownership is released there and acquired on the graphics queue.
+    pub unsafe fn upload(
+        &self,
+        buffers: &[vk::Buffer],
+        images: &[vk::Image],
+        record: impl FnOnce(vk::CommandBuffer),
+    ) {
+        let _span = debug_span!("upload", buffers = buffers.len(), images = images.len()).entered();
+        let families = self.queues.families;
+        let separate = families.transfer != families.graphics;
+        let (src_family, dst_family) = if separate {
+            (families.transfer, families.graphics)
+        } else {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        };
+        // the release ignores the destination access and the acquire the source access
+        let buffer_barriers: Vec<_> = buffers
+            .iter()
+            .map(|&buffer| {
+                vk::BufferMemoryBarrier::default()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                    .src_queue_family_index(src_family)
+                    .dst_queue_family_index(dst_family)
+                    .buffer(buffer)
+                    .size(vk::WHOLE_SIZE)
+            })
+            .collect();
+        let image_barriers: Vec<_> = images
+            .iter()
+            .map(|&image| {
+                vk::ImageMemoryBarrier::default()
+                    .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .src_queue_family_index(src_family)
+                    .dst_queue_family_index(dst_family)
+                    .image(image)
+                    .subresource_range(color_range())
+            })
+            .collect();
+        let barrier = |cmd, src_stage, dst_stage| {
+            self.device.cmd_pipeline_barrier(
+                cmd,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &buffer_barriers,
+                &image_barriers,
+            );
+        };
+        one_time_submit(
+            self.device,
+            self.transfer_pool,
+            self.queues.transfer,
+            |cmd| {
+                record(cmd);
+                // a transfer only queue doesn't know about later stages
+                let dst_stage = if separate {
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE
+                } else {
+                    vk::PipelineStageFlags::ALL_COMMANDS
+                };
+                barrier(cmd, vk::PipelineStageFlags::TRANSFER, dst_stage);
+            },
+        );
+        // the release is known to be done, as one_time_submit waits for its submission
+        if separate {
+            self.one_time_submit(|cmd| {
+                barrier(
+                    cmd,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                )
+            });
+        }
+    }
+
+    /// Uploads `data` into a new device local buffer with `usage`.
+    pub unsafe fn upload_buffer<T: Copy>(
+        &self,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+        name: &str,
+    ) -> (vk::Buffer, vk::DeviceMemory) {
+        let size = size_of_val(data) as u64;
+        let staging = self.make_staging(data);
+        let buff = self.make_buffer(
+            size,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::BufferUsageFlags::TRANSFER_DST | usage,
+            name,
+        );
+        self.upload(&[buff.0], &[], |cmd| {
+            let copy_reg = [vk::BufferCopy::default().size(size)];
+            self.device
+                .cmd_copy_buffer(cmd, staging.0, buff.0, &copy_reg);
+        });
+        self.device.destroy_buffer(staging.0, None);
+        self.device.free_memory(staging.1, None);
+        buff
+    }
+}
+
+/// A host visible, persistently mapped buffer that is rewritten by the CPU every frame. Each
+/// frame in flight needs its own, as the GPU may still be reading the previous frame's contents.
+pub(crate) struct DynBuffer {
+    pub buff: vk::Buffer,
+    memory: vk::DeviceMemory,
+    ptr: *mut u8,
+    capacity: usize,
+    usage: vk::BufferUsageFlags,
+    name: &'static str,
+}
+
+impl Default for DynBuffer {
+    fn default() -> Self {
+        DynBuffer {
+            buff: vk::Buffer::default(),
+            memory: vk::DeviceMemory::default(),
+            ptr: std::ptr::null_mut(),
+            capacity: 0,
+            usage: vk::BufferUsageFlags::empty(),
+            name: "",
+        }
+    }
+}
+
+impl DynBuffer {
+    pub unsafe fn new(
+        gpu: Gpu,
+        capacity: usize,
+        usage: vk::BufferUsageFlags,
+        name: &'static str,
+    ) -> Self {
+        use vk::MemoryPropertyFlags as mpf;
+        let (buff, memory) = gpu.make_buffer(
+            capacity as u64,
+            mpf::HOST_VISIBLE | mpf::HOST_COHERENT,
+            usage,
+            name,
+        );
+        let ptr = gpu
+            .device
+            .map_memory(memory, 0, capacity as u64, vk::MemoryMapFlags::empty())
+            .expect("Failed to map dynamic buffer.") as *mut u8;
+        DynBuffer {
+            buff,
+            memory,
+            ptr,
+            capacity,
+            usage,
+            name,
+        }
+    }
+
+    /// Copies `data` to the start of the buffer, growing it first if it is too small. Only call
+    /// once the GPU is done with this buffer, i.e. after waiting for the frame that last used it.
+    pub unsafe fn write<T: Copy>(&mut self, gpu: Gpu, data: &[T]) {
+        let size = size_of_val(data);
+        if size > self.capacity {
+            self.destroy(gpu.device);
+            *self = DynBuffer::new(gpu, size.next_power_of_two(), self.usage, self.name);
+        }
+        std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, self.ptr, size);
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        device.destroy_buffer(self.buff, None);
+        device.free_memory(self.memory, None);
+    }
+}
+
+pub(crate) unsafe fn load_shader_module(device: &Device) -> vk::ShaderModule {
+    let shader_code = read_spv(&mut Cursor::new(
+        &include_bytes!(
+            "../../shaders/target/spirv-builder/spirv-unknown-spv1.0/release/deps/shader_crate.spv"
+        )[..],
+    ))
+    .expect("Failed to read shader spv.");
+    let info = vk::ShaderModuleCreateInfo::default().code(&shader_code);
+    device
+        .create_shader_module(&info, None)
+        .expect("Failed to create shader module.")
+}
+
+pub(crate) fn find_memory_type(
+    mem_props: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    props: vk::MemoryPropertyFlags,
+) -> u32 {
+    mem_props.memory_types[..mem_props.memory_type_count as usize]
+        .iter()
+        .enumerate()
+        .find(|(i, mt)| (type_bits & (1 << i)) > 0 && (mt.property_flags & props) == props)
+        .expect("Failed to find suitable memory type.")
+        .0 as u32
+}
+
+pub(crate) unsafe fn make_buffer(
+    device: &Device,
+    mem_props: &vk::PhysicalDeviceMemoryProperties,
+    size: u64,
+    props: vk::MemoryPropertyFlags,
+    usage: vk::BufferUsageFlags,
+    name: &str,
+) -> (vk::Buffer, vk::DeviceMemory) {
+    let buff_info = vk::BufferCreateInfo::default()
+        .size(size)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buff = device
+        .create_buffer(&buff_info, None)
+        .expect("Failed to create buffer.");
+    name_object(buff, name);
+    let mem_reqs = device.get_buffer_memory_requirements(buff);
+    let alloc_info = vk::MemoryAllocateInfo::default()
+        .memory_type_index(find_memory_type(
+            mem_props,
+            mem_reqs.memory_type_bits,
+            props,
+        ))
+        .allocation_size(mem_reqs.size);
+    let memory = device
+        .allocate_memory(&alloc_info, None)
+        .expect("Failed to allocate buffer memory.");
+    device
+        .bind_buffer_memory(buff, memory, 0)
+        .expect("Failed to bind memory.");
+    (buff, memory)
+}
+
+/// Creates a device local 2D image with a single mip level and a view covering all of it.
+pub(crate) unsafe fn make_image(
+    device: &Device,
+    mem_props: &vk::PhysicalDeviceMemoryProperties,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    aspect: vk::ImageAspectFlags,
+    name: &str,
+) -> (vk::Image, vk::DeviceMemory, vk::ImageView) {
+    let image_info = vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(format)
+        .extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    let image = device
+        .create_image(&image_info, None)
+        .expect("Failed to create image.");
+    name_object(image, name);
+    let mem_reqs = device.get_image_memory_requirements(image);
+    let alloc_info = vk::MemoryAllocateInfo::default()
+        .allocation_size(mem_reqs.size)
+        .memory_type_index(find_memory_type(
+            mem_props,
+            mem_reqs.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ));
+    let memory = device
+        .allocate_memory(&alloc_info, None)
+        .expect("Failed to allocate image memory.");
+    device
+        .bind_image_memory(image, memory, 0)
+        .expect("Failed to bind image memory.");
+    let view_info = vk::ImageViewCreateInfo::default()
+        .image(image)
+        .format(format)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .subresource_range(
+            vk::ImageSubresourceRange::default()
+                .aspect_mask(aspect)
+                .level_count(1)
+                .layer_count(1),
+        );
+    let view = device
+        .create_image_view(&view_info, None)
+        .expect("Failed to create image view.");
+    name_object(view, name);
+    (image, memory, view)
+}
+
+/// Creates a host visible buffer and fills it with `data`, for use as a transfer source.
+pub(crate) unsafe fn make_staging<T: Copy>(
+    device: &Device,
+    mem_props: &vk::PhysicalDeviceMemoryProperties,
+    data: &[T],
+) -> (vk::Buffer, vk::DeviceMemory) {
+    use vk::{BufferUsageFlags as buf, MemoryPropertyFlags as mpf};
+    let size = size_of_val(data) as u64;
+    let staging = make_buffer(
+        device,
+        mem_props,
+        size,
+        mpf::HOST_VISIBLE | mpf::HOST_COHERENT,
+        buf::TRANSFER_SRC,
+        "staging",
+    );
+    let ptr = device
+        .map_memory(staging.1, 0, size, vk::MemoryMapFlags::empty())
+        .expect("Failed to map staging buffer memory") as *mut T;
+    std::slice::from_raw_parts_mut(ptr, data.len()).copy_from_slice(data);
+    device.unmap_memory(staging.1);
+    staging
+}
+
+/// Records `record` into a temporary command buffer, submits it and blocks until it is done.
+pub(crate) unsafe fn one_time_submit(
+    device: &Device,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    record: impl FnOnce(vk::CommandBuffer),
+) {
+    let create_info = vk::CommandBufferAllocateInfo::default()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1)
+        .command_pool(command_pool);
+    let command_buff = device
+        .allocate_command_buffers(&create_info)
+        .expect("Failed to allocate one time command buffer.");
+    let begin_info =
+        vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device
+        .begin_command_buffer(command_buff[0], &begin_info)
+        .expect("Failed to begin command buffer.");
+    record(command_buff[0]);
+    device
+        .end_command_buffer(command_buff[0])
+        .expect("Failed to end command buffer.");
+    // waits for this submission only, not for the frames in flight on the same queue
+    let fence = device
+        .create_fence(&vk::FenceCreateInfo::default(), None)
+        .expect("Failed to create fence.");
+    let submit_info = [vk::SubmitInfo::default().command_buffers(&command_buff)];
+    device
+        .queue_submit(queue, &submit_info, fence)
+        .expect("Failed to submit to queue.");
+    device
+        .wait_for_fences(&[fence], true, u64::MAX)
+        .expect("Failed to wait for fences.");
+    device.destroy_fence(fence, None);
+    device.free_command_buffers(command_pool, &command_buff);
+}
@@ -0,0 +1,252 @@
+//! Opt-in Vulkan validation. The Khronos validation layer is enabled when requested and installed,
+//! and its messages, like any other the driver or loader sends through `VK_EXT_debug_utils`, are
+//! forwarded to `tracing` at the matching level.
+use ash::{
+    ext::{debug_utils, validation_features},
+    vk, Entry, Instance,
+};
+use serde::Deserialize;
+use std::{
+    env,
+    ffi::CStr,
+    os::raw::{c_char, c_void},
+};
+use tracing::{error, info, trace, warn};
+
+/// Overrides the validation config: `0` or `off` disables validation, anything else enables it
+/// along with the comma separated options in it: `sync`, `best-practices` and a minimum severity
+/// of `verbose`, `info`, `warning` or `error`. For example `GAEM_VALIDATION=sync,info`.
+pub const VALIDATION_ENV: &str = "GAEM_VALIDATION";
+
+const VALIDATION_LAYER: &CStr = c"VK_LAYER_KHRONOS_validation";
+
+/// The least severe messages that are logged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationSeverity {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+impl ValidationSeverity {
+    fn to_vk(self) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+        use vk::DebugUtilsMessageSeverityFlagsEXT as sev;
+        [sev::VERBOSE, sev::INFO, sev::WARNING, sev::ERROR][self as usize..]
+            .iter()
+            .fold(sev::empty(), |all, s| all | *s)
+    }
+}
+
+/// Validation is off by default, as it slows everything down and needs the Vulkan SDK.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    pub enabled: bool,
+    /// Checks for missing or wrong synchronization, which is slow even by validation standards.
+    pub synchronization: bool,
+    /// Warns about valid but slow API usage.
+    pub best_practices: bool,
+    pub min_severity: ValidationSeverity,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            enabled: false,
+            synchronization: false,
+            best_practices: false,
+            min_severity: ValidationSeverity::Warning,
+        }
+    }
+}
+
+impl ValidationConfig {
+    /// Applies `GAEM_VALIDATION` if it is set.
+    pub(crate) fn with_env(mut self) -> Self {
+        let Ok(var) = env::var(VALIDATION_ENV) else {
+            return self;
+        };
+        self.enabled = !matches!(var.trim(), "0" | "off" | "false");
+        for option in var.split(',').map(str::trim) {
+            match option {
+                "sync" => self.synchronization = true,
+                "best-practices" => self.best_practices = true,
+                "verbose" => self.min_severity = ValidationSeverity::Verbose,
+                "info" => self.min_severity = ValidationSeverity::Info,
+                "warning" => self.min_severity = ValidationSeverity::Warning,
+                "error" => self.min_severity = ValidationSeverity::Error,
+                // only switch validation on or off
+                "" | "1" | "on" | "true" | "0" | "off" | "false" => {}
+                _ => warn!(
+                    option,
+                    "Ignoring unknown {VALIDATION_ENV} option. Accepted are 0, off, false, 1, on, \
+                     true, sync, best-practices, verbose, info, warning and error."
+                ),
+            }
+        }
+        self
+    }
+
+    fn feature_enables(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut enables = Vec::new();
+        if self.synchronization {
+            enables.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+        if self.best_practices {
+            enables.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+        }
+        enables
+    }
+}
+
+/// The layers and extensions to create the instance with, leaving out whatever isn't installed.
+pub(crate) struct InstanceValidation {
+    config: ValidationConfig,
+    layers: Vec<*const c_char>,
+    extensions: Vec<*const c_char>,
+    /// Whether a messenger can be created.
+    debug_utils: bool,
+    enables: Vec<vk::ValidationFeatureEnableEXT>,
+}
+
+impl InstanceValidation {
+    pub unsafe fn new(entry: &Entry, config: ValidationConfig) -> Self {
+        let has_extension = |layer, name| {
+            entry
+                .enumerate_instance_extension_properties(layer)
+                .unwrap_or_default()
+                .iter()
+                .any(|p| p.extension_name_as_c_str() == Ok(name))
+        };
+        let mut validation = InstanceValidation {
+            config,
+            layers: Vec::new(),
+            extensions: Vec::new(),
+            debug_utils: has_extension(None, debug_utils::NAME),
+            enables: Vec::new(),
+        };
+        if validation.debug_utils {
+            validation.extensions.push(debug_utils::NAME.as_ptr());
+        }
+        if !config.enabled {
+            return validation;
+        }
+        let has_layer = entry
+            .enumerate_instance_layer_properties()
+            .unwrap_or_default()
+            .iter()
+            .any(|p| p.layer_name_as_c_str() == Ok(VALIDATION_LAYER));
+        if !has_layer {
+            warn!("Validation was requested, but {VALIDATION_LAYER:?} isn't installed.");
+            return validation;
+        }
+        validation.layers.push(VALIDATION_LAYER.as_ptr());
+        validation.enables = config.feature_enables();
+        // provided by the layer itself
+        if !validation.enables.is_empty() {
+            if has_extension(Some(VALIDATION_LAYER), validation_features::NAME) {
+                validation
+                    .extensions
+                    .push(validation_features::NAME.as_ptr());
+            } else {
+                warn!("The validation layer is too old for synchronization or best practices validation.");
+                validation.enables.clear();
+            }
+        }
+        validation
+    }
+
+    /// Whether objects can be named and command buffers labeled.
+    pub fn debug_utils(&self) -> bool {
+        self.debug_utils
+    }
+
+    fn messenger_info(&self) -> Option<vk::DebugUtilsMessengerCreateInfoEXT<'static>> {
+        use vk::DebugUtilsMessageTypeFlagsEXT as ty;
+        (self.debug_utils && self.config.enabled).then(|| {
+            vk::DebugUtilsMessengerCreateInfoEXT::default()
+                .message_severity(self.config.min_severity.to_vk())
+                .message_type(ty::GENERAL | ty::VALIDATION | ty::PERFORMANCE)
+                .pfn_user_callback(Some(debug_callback))
+        })
+    }
+
+    /// Creates the instance with `info` and `extensions` on top of the validation's own layers,
+    /// extensions and `p_next` chain.
+    pub unsafe fn create_instance(
+        &self,
+        entry: &Entry,
+        info: vk::InstanceCreateInfo,
+        extensions: &[*const c_char],
+    ) -> Instance {
+        let mut names = extensions.to_vec();
+        names.extend(&self.extensions);
+        let mut info = info
+            .enabled_layer_names(&self.layers)
+            .enabled_extension_names(&names);
+        // also reports problems creating and destroying the instance, which no messenger sees
+        let mut messenger_info = self.messenger_info();
+        if let Some(messenger_info) = &mut messenger_info {
+            info = info.push_next(messenger_info);
+        }
+        let mut features =
+            vk::ValidationFeaturesEXT::default().enabled_validation_features(&self.enables);
+        if !self.enables.is_empty() {
+            info = info.push_next(&mut features);
+        }
+        entry
+            .create_instance(&info, None)
+            .expect("Failed to create instance.")
+    }
+}
+
+/// Forwards validation messages to `tracing` for as long as it lives.
+#[derive(Default)]
+pub(crate) struct DebugMessenger {
+    loader: Option<debug_utils::Instance>,
+    messenger: vk::DebugUtilsMessengerEXT,
+}
+
+impl DebugMessenger {
+    /// Does nothing unless validation was enabled on `instance`.
+    pub unsafe fn new(entry: &Entry, instance: &Instance, validation: &InstanceValidation) -> Self {
+        let Some(info) = validation.messenger_info() else {
+            return DebugMessenger::default();
+        };
+        let loader = debug_utils::Instance::new(entry, instance);
+        let messenger = loader
+            .create_debug_utils_messenger(&info, None)
+            .expect("Failed to create debug messenger.");
+        DebugMessenger {
+            loader: Some(loader),
+            messenger,
+        }
+    }
+
+    pub unsafe fn destroy(&self) {
+        if let Some(loader) = &self.loader {
+            loader.destroy_debug_utils_messenger(self.messenger, None);
+        }
+    }
+}
+
+unsafe extern "system" fn debug_callback(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    types: vk::DebugUtilsMessageTypeFlagsEXT,
+    data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    _user_data: *mut c_void,
+) -> vk::Bool32 {
+    use vk::DebugUtilsMessageSeverityFlagsEXT as sev;
+    let data = &*data;
+    let message = data.message_as_c_str().unwrap_or(c"").to_string_lossy();
+    match severity {
+        sev::ERROR => error!(?types, "{message}"),
+        sev::WARNING => warn!(?types, "{message}"),
+        sev::INFO => info!(?types, "{message}"),
+        _ => trace!(?types, "{message}"),
+    }
+    // the call that caused the message must not be aborted
+    vk::FALSE
+}
@@ -0,0 +1,594 @@
+//! Everything that exists once per window: its surface and swapchain, the render graph sized to
+//! it, its frames in flight, and the output pass, tone mapper and UI drawing into it. The device
+//! and the scene pipelines are shared between windows and passed in as `Shared`.
+use crate::{
+    app::Shared,
+    debug::DebugRenderer,
+    frame::FrameContext,
+    graph::{Access, ImageDesc, ImageId, PassId, RenderGraph},
+    label::name_object,
+    pipeline::PassTarget,
+    profiler::GpuProfiler,
+    sprite::{SpriteBatch, SpriteRenderer},
+    swapchain::{PresentMode, SurfaceSupport},
+    text::{TextBatch, TextRenderer},
+    texture::Textures,
+    tonemap::{OutputMode, Tonemapper, HDR_FORMAT},
+    ui::EguiRenderer,
+    util::load_shader_module,
+};
+use ash::vk;
+use glam::Mat4;
+use tracing::{debug, debug_span, info};
+use winit::{dpi::PhysicalSize, window::Window};
+
+/// The render graph of a frame, with the handles the passes are recorded with.
+#[derive(Default)]
+pub(crate) struct FrameGraph {
+    pub graph: RenderGraph,
+    /// The scene is rendered here, in linear light with no upper limit.
+    pub hdr: ImageId,
+    pub depth: ImageId,
+    pub swapchain: ImageId,
+    pub scene: PassId,
+    pub debug: PassId,
+    pub output: PassId,
+}
+
+pub(crate) struct AppWindow {
+    pub window: Window,
+    pub surface: vk::SurfaceKHR,
+    pub swapchain: vk::SwapchainKHR,
+    pub swap_imgs: Vec<vk::Image>,
+    pub swap_img_views: Vec<vk::ImageView>,
+    /// Tone maps the scene onto a swapchain image, draws the UI over it and transitions the image
+    /// for presenting. Null with dynamic rendering, as are all framebuffers.
+    pub output_pass: vk::RenderPass,
+    pub output_framebuffers: Vec<vk::Framebuffer>,
+    pub format: vk::SurfaceFormatKHR,
+    pub extent: vk::Extent2D,
+    /// The mode actually in use, which differs from the config's if the surface lacks it.
+    pub present_mode: PresentMode,
+    pub output_mode: OutputMode,
+    pub frame_graph: FrameGraph,
+    pub scene_framebuffer: vk::Framebuffer,
+    pub debug_framebuffer: vk::Framebuffer,
+    pub tonemapper: Tonemapper,
+    pub frames: Vec<FrameContext>,
+    pub cur_frame: usize,
+    pub resized: Option<PhysicalSize<u32>>,
+    pub minimized: bool,
+    pub sprites: SpriteBatch,
+    pub texts: TextBatch,
+    pub view_proj: Mat4,
+    pub egui_state: egui_winit::State,
+    pub egui_renderer: EguiRenderer,
+}
+
+impl AppWindow {
+    /// Sets up rendering to `window` through `surface`, which the device must be able to present
+    /// to.
+    pub unsafe fn new(
+        shared: &Shared,
+        window: Window,
+        surface: vk::SurfaceKHR,
+        output_mode: OutputMode,
+        textures: &Textures,
+        profiler: &GpuProfiler,
+    ) -> Self {
+        let egui_state = egui_winit::State::new(
+            egui::Context::default(),
+            egui::ViewportId::ROOT,
+            &window,
+            Some(window.scale_factor() as f32),
+            window.theme(),
+            None,
+        );
+        let mut win = AppWindow {
+            window,
+            surface,
+            swapchain: vk::SwapchainKHR::null(),
+            swap_imgs: Vec::new(),
+            swap_img_views: Vec::new(),
+            output_pass: vk::RenderPass::null(),
+            output_framebuffers: Vec::new(),
+            format: vk::SurfaceFormatKHR::default(),
+            extent: vk::Extent2D::default(),
+            present_mode: PresentMode::Fifo,
+            output_mode,
+            frame_graph: FrameGraph::default(),
+            scene_framebuffer: vk::Framebuffer::null(),
+            debug_framebuffer: vk::Framebuffer::null(),
+            tonemapper: Tonemapper::default(),
+            frames: Vec::new(),
+            cur_frame: 0,
+            resized: None,
+            minimized: false,
+            sprites: SpriteBatch::default(),
+            texts: TextBatch::default(),
+            view_proj: Mat4::IDENTITY,
+            egui_state,
+            egui_renderer: EguiRenderer::default(),
+        };
+        win.pick_surface_format(shared);
+        if !shared.rendering.is_dynamic() {
+            win.create_output_pass(shared);
+        }
+        let device = &shared.device;
+        let gpu = shared.gpu();
+        win.frames = (0..shared.frames_in_flight)
+            .map(|_| FrameContext::new(device, shared.command_pool, shared.sync.uses_fences()))
+            .collect();
+        SpriteRenderer::attach_frames(gpu, &mut win.frames);
+        TextRenderer::attach_frames(gpu, &mut win.frames);
+        DebugRenderer::attach_frames(gpu, &mut win.frames);
+        profiler.attach_frames(device, &mut win.frames);
+        let shader_module = load_shader_module(device);
+        let output_target = win.output_target(shared);
+        win.tonemapper = Tonemapper::new(device, shader_module, output_target);
+        win.egui_renderer =
+            EguiRenderer::new(gpu, shader_module, output_target, textures, &mut win.frames);
+        device.destroy_shader_module(shader_module, None);
+        win.create_swapchain(shared);
+        win.begin_ui_pass();
+        win
+    }
+
+    /// Begins the scene pass, clearing the HDR and depth targets.
+    pub unsafe fn begin_scene(&self, shared: &Shared, cmd: vk::CommandBuffer) {
+        let mut clear_color = [vk::ClearValue::default(); 2];
+        clear_color[0].color.float32 = [0.0, 0.0, 0.0, 1.0];
+        clear_color[1].depth_stencil.depth = 1.0;
+        let render_area = vk::Rect2D::default().extent(self.extent);
+        if !shared.rendering.is_dynamic() {
+            let pass_info = vk::RenderPassBeginInfo::default()
+                .render_pass(shared.render_pass)
+                .framebuffer(self.scene_framebuffer)
+                .render_area(render_area)
+                .clear_values(&clear_color);
+            shared
+                .device
+                .cmd_begin_render_pass(cmd, &pass_info, vk::SubpassContents::INLINE);
+            return;
+        }
+        let graph = &self.frame_graph;
+        let color = [vk::RenderingAttachmentInfo::default()
+            .image_view(graph.graph.view(graph.hdr))
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(clear_color[0])];
+        let depth = vk::RenderingAttachmentInfo::default()
+            .image_view(graph.graph.view(graph.depth))
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .clear_value(clear_color[1]);
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(&color)
+            .depth_attachment(&depth);
+        shared.rendering.begin(&shared.device, cmd, &rendering_info);
+    }
+
+    /// Begins the debug pass over what the scene pass drew.
+    pub unsafe fn begin_debug(&self, shared: &Shared, cmd: vk::CommandBuffer) {
+        let render_area = vk::Rect2D::default().extent(self.extent);
+        if !shared.rendering.is_dynamic() {
+            let pass_info = vk::RenderPassBeginInfo::default()
+                .render_pass(shared.debug_pass)
+                .framebuffer(self.debug_framebuffer)
+                .render_area(render_area);
+            shared
+                .device
+                .cmd_begin_render_pass(cmd, &pass_info, vk::SubpassContents::INLINE);
+            return;
+        }
+        let graph = &self.frame_graph;
+        let color = [vk::RenderingAttachmentInfo::default()
+            .image_view(graph.graph.view(graph.hdr))
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)];
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(&color);
+        shared.rendering.begin(&shared.device, cmd, &rendering_info);
+    }
+
+    /// Begins the output pass on the acquired swapchain image, which is fully overwritten.
+    pub unsafe fn begin_output(&self, shared: &Shared, cmd: vk::CommandBuffer, img_idx: usize) {
+        let render_area = vk::Rect2D::default().extent(self.extent);
+        if !shared.rendering.is_dynamic() {
+            let pass_info = vk::RenderPassBeginInfo::default()
+                .render_pass(self.output_pass)
+                .framebuffer(self.output_framebuffers[img_idx])
+                .render_area(render_area);
+            shared
+                .device
+                .cmd_begin_render_pass(cmd, &pass_info, vk::SubpassContents::INLINE);
+            return;
+        }
+        let color = [vk::RenderingAttachmentInfo::default()
+            .image_view(self.swap_img_views[img_idx])
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)];
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(&color);
+        shared.rendering.begin(&shared.device, cmd, &rendering_info);
+    }
+
+    pub unsafe fn end_pass(&self, shared: &Shared, cmd: vk::CommandBuffer) {
+        if shared.rendering.is_dynamic() {
+            shared.rendering.end(&shared.device, cmd);
+        } else {
+            shared.device.cmd_end_render_pass(cmd);
+        }
+    }
+
+    /// Replaces the swapchain after a resize or once it no longer matches the surface. The old
+    /// swapchain is handed to the new one, and the render passes, which pipelines are built
+    /// against, are kept since they only depend on the format.
+    pub unsafe fn recreate_swapchain(&mut self, shared: &Shared) {
+        let (capabilities, _, _) = shared.swap_support(self.surface);
+        // a minimized window can't have a swapchain; rendering pauses until it is restored
+        self.minimized =
+            capabilities.current_extent.width == 0 || capabilities.current_extent.height == 0;
+        if self.minimized {
+            debug!("Window minimized, pausing rendering.");
+            return;
+        }
+        let _span = debug_span!("recreate_swapchain").entered();
+        shared.device.device_wait_idle().unwrap();
+        self.clean_swapchain(shared);
+        self.create_swapchain(shared);
+    }
+
+    /// Changes the swapchain format and color space. Only the output pass and the pipelines drawing
+    /// into it depend on the format, so the scene is left alone.
+    pub unsafe fn set_output_mode(&mut self, shared: &Shared, mode: OutputMode) {
+        self.output_mode = mode;
+        let old_format = self.format;
+        self.pick_surface_format(shared);
+        if (self.format.format, self.format.color_space)
+            != (old_format.format, old_format.color_space)
+        {
+            shared.device.device_wait_idle().unwrap();
+            if !shared.rendering.is_dynamic() {
+                shared.device.destroy_render_pass(self.output_pass, None);
+                self.create_output_pass(shared);
+            }
+            let shader_module = load_shader_module(&shared.device);
+            self.tonemapper.create_pipeline(
+                &shared.device,
+                shader_module,
+                self.output_target(shared),
+            );
+            self.egui_renderer.create_pipeline(
+                &shared.device,
+                shader_module,
+                self.output_target(shared),
+            );
+            shared.device.destroy_shader_module(shader_module, None);
+        }
+        self.recreate_swapchain(shared);
+    }
+
+    pub unsafe fn pick_surface_format(&mut self, shared: &Shared) {
+        let (_, formats, _) = shared.swap_support(self.surface);
+        // fall back to SDR, and to any format at all if the surface has no sRGB one
+        self.format = match self.output_mode.pick_format(&formats) {
+            Some(format) => format,
+            None => {
+                self.output_mode = OutputMode::Sdr;
+                OutputMode::Sdr.pick_format(&formats).unwrap_or(formats[0])
+            }
+        };
+    }
+
+    pub unsafe fn create_swapchain(&mut self, shared: &Shared) {
+        let (capabilities, _, modes) = shared.swap_support(self.surface);
+        // some platforms, like Wayland, size the surface after the swapchain, which then has to
+        // follow the window, including when its scale factor changes
+        self.extent = if capabilities.current_extent.width == u32::MAX {
+            let size = self.window.inner_size();
+            let (min, max) = (capabilities.min_image_extent, capabilities.max_image_extent);
+            vk::Extent2D {
+                width: size.width.clamp(min.width, max.width),
+                height: size.height.clamp(min.height, max.height),
+            }
+        } else {
+            capabilities.current_extent
+        };
+        let old_swapchain = self.swapchain;
+        // rendering and presenting from different families shares the images, rather than
+        // transferring their ownership every frame
+        let families = shared.queues.families;
+        let (sharing_mode, sharing_families) = if families.graphics == families.present {
+            (vk::SharingMode::EXCLUSIVE, vec![])
+        } else {
+            (
+                vk::SharingMode::CONCURRENT,
+                vec![families.graphics, families.present],
+            )
+        };
+        self.swapchain = {
+            let support = SurfaceSupport::new(&capabilities, &modes);
+            self.present_mode = support.present_mode(&shared.swap_config);
+            let img_count = support.image_count(&shared.swap_config);
+            let info = vk::SwapchainCreateInfoKHR::default()
+                .image_format(self.format.format)
+                .present_mode(self.present_mode.to_vk())
+                .min_image_count(img_count)
+                .image_extent(self.extent)
+                .image_color_space(self.format.color_space)
+                .image_array_layers(1)
+                .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+                .image_sharing_mode(sharing_mode)
+                .queue_family_indices(&sharing_families)
+                .pre_transform(capabilities.current_transform)
+                .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+                .clipped(true)
+                .surface(self.surface)
+                .old_swapchain(old_swapchain);
+            shared
+                .swap_device
+                .create_swapchain(&info, None)
+                .expect("Failed to create swapchain.")
+        };
+        shared.swap_device.destroy_swapchain(old_swapchain, None);
+        info!(
+            extent = ?(self.extent.width, self.extent.height),
+            format = ?self.format.format,
+            color_space = ?self.format.color_space,
+            present_mode = ?self.present_mode,
+            "Created swapchain"
+        );
+        self.swap_imgs = {
+            shared
+                .swap_device
+                .get_swapchain_images(self.swapchain)
+                .expect("Failed to retrieve swapchain image handles.")
+        };
+        for (i, image) in self.swap_imgs.iter().enumerate() {
+            name_object(*image, &format!("swapchain image {i}"));
+        }
+        self.swap_img_views = self
+            .swap_imgs
+            .iter()
+            .enumerate()
+            .map(|(idx, i)| {
+                let info = vk::ImageViewCreateInfo::default()
+                    .image(*i)
+                    .format(self.format.format)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .components(
+                        vk::ComponentMapping::default()
+                            .r(vk::ComponentSwizzle::IDENTITY)
+                            .g(vk::ComponentSwizzle::IDENTITY)
+                            .b(vk::ComponentSwizzle::IDENTITY)
+                            .a(vk::ComponentSwizzle::IDENTITY),
+                    )
+                    .subresource_range(
+                        vk::ImageSubresourceRange::default()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .base_mip_level(0)
+                            .level_count(1)
+                            .base_array_layer(0)
+                            .layer_count(1),
+                    );
+                unsafe {
+                    let view = shared
+                        .device
+                        .create_image_view(&info, None)
+                        .expect("Failed to create image view.");
+                    name_object(view, &format!("swapchain image {idx}"));
+                    view
+                }
+            })
+            .collect();
+        self.build_graph(shared);
+        let graph = &self.frame_graph;
+        self.tonemapper
+            .set_input(&shared.device, graph.graph.view(graph.hdr));
+        if shared.rendering.is_dynamic() {
+            return;
+        }
+        self.scene_framebuffer = {
+            let attachments = [graph.graph.view(graph.hdr), graph.graph.view(graph.depth)];
+            let framebuffer_info = vk::FramebufferCreateInfo::default()
+                .render_pass(shared.render_pass)
+                .attachments(&attachments)
+                .width(self.extent.width)
+                .height(self.extent.height)
+                .layers(1);
+            shared
+                .device
+                .create_framebuffer(&framebuffer_info, None)
+                .expect("Failed to create scene framebuffer.")
+        };
+        self.debug_framebuffer = {
+            let attachments = [graph.graph.view(graph.hdr)];
+            let framebuffer_info = vk::FramebufferCreateInfo::default()
+                .render_pass(shared.debug_pass)
+                .attachments(&attachments)
+                .width(self.extent.width)
+                .height(self.extent.height)
+                .layers(1);
+            shared
+                .device
+                .create_framebuffer(&framebuffer_info, None)
+                .expect("Failed to create debug framebuffer.")
+        };
+        self.output_framebuffers = self
+            .swap_img_views
+            .iter()
+            .map(|view| unsafe {
+                let attachments = [*view];
+                let framebuffer_info = vk::FramebufferCreateInfo::default()
+                    .render_pass(self.output_pass)
+                    .attachments(&attachments)
+                    .width(self.extent.width)
+                    .height(self.extent.height)
+                    .layers(1);
+                shared
+                    .device
+                    .create_framebuffer(&framebuffer_info, None)
+                    .expect("Failed to create output framebuffer.")
+            })
+            .collect();
+    }
+
+    /// What the tone mapping and UI pipelines draw into.
+    pub fn output_target(&self, shared: &Shared) -> PassTarget {
+        if shared.rendering.is_dynamic() {
+            PassTarget::Dynamic {
+                color: self.format.format,
+                depth: vk::Format::UNDEFINED,
+            }
+        } else {
+            PassTarget::RenderPass(self.output_pass)
+        }
+    }
+
+    /// Declares the frame's passes and the resources they use, and compiles the graph, which
+    /// creates the scene targets at the swapchain's extent.
+    pub unsafe fn build_graph(&mut self, shared: &Shared) {
+        let mut graph = RenderGraph::new(shared.sync.sync2());
+        let hdr = graph.create_image(
+            "hdr",
+            ImageDesc {
+                format: HDR_FORMAT,
+                extent: self.extent,
+                aspect: vk::ImageAspectFlags::COLOR,
+            },
+        );
+        let depth = graph.create_image(
+            "depth",
+            ImageDesc {
+                format: shared.depth_format,
+                extent: self.extent,
+                aspect: vk::ImageAspectFlags::DEPTH,
+            },
+        );
+        // the acquired image's old contents are discarded, and it is only ready once the acquire
+        // semaphore, waited on at color attachment output, is signaled
+        let swapchain = graph.import_image(
+            "swapchain",
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        );
+        graph.export_image(swapchain, Access::Present);
+        let scene = graph
+            .add_pass("scene")
+            .image(hdr, Access::ColorAttachment)
+            .image(depth, Access::DepthAttachment)
+            .build();
+        // debug lines go over the scene before it is tone mapped, so they look the same in every
+        // output mode
+        let debug = graph
+            .add_pass("debug")
+            .image(hdr, Access::ColorAttachmentLoad)
+            .build();
+        let output = graph
+            .add_pass("output")
+            .image(hdr, Access::Sampled)
+            .image(swapchain, Access::ColorAttachment)
+            .build();
+        graph.compile(&shared.device, &shared.mem_props);
+        self.frame_graph = FrameGraph {
+            graph,
+            hdr,
+            depth,
+            swapchain,
+            scene,
+            debug,
+            output,
+        };
+    }
+
+    /// Creates the output render pass for the surface format. The render graph transitions the
+    /// swapchain image for presenting afterwards.
+    pub unsafe fn create_output_pass(&mut self, shared: &Shared) {
+        self.output_pass = {
+            let attachment_desc = [vk::AttachmentDescription::default()
+                .format(self.format.format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+            let attachment_ref = [vk::AttachmentReference::default()
+                .attachment(0)
+                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+            let subpass = [vk::SubpassDescription::default()
+                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                .color_attachments(&attachment_ref)];
+            let info = vk::RenderPassCreateInfo::default()
+                .attachments(&attachment_desc)
+                .subpasses(&subpass);
+            let render_pass = shared
+                .device
+                .create_render_pass(&info, None)
+                .expect("Failed to create output render pass.");
+            name_object(render_pass, "output");
+            render_pass
+        };
+    }
+
+    /// Destroys everything created along with the swapchain, except the swapchain itself, which
+    /// is retired by the next one.
+    pub unsafe fn clean_swapchain(&mut self, shared: &Shared) {
+        shared
+            .device
+            .destroy_framebuffer(self.scene_framebuffer, None);
+        shared
+            .device
+            .destroy_framebuffer(self.debug_framebuffer, None);
+        for framebuffer in self.output_framebuffers.drain(..) {
+            shared.device.destroy_framebuffer(framebuffer, None);
+        }
+        for i in 0..self.swap_img_views.len() {
+            shared
+                .device
+                .destroy_image_view(self.swap_img_views[i], None);
+        }
+        self.frame_graph.graph.destroy(&shared.device);
+    }
+
+    /// Starts the egui pass that UI code adds to until the next `render`. The screen is taken
+    /// from the swapchain extent, which can lag behind the window size while resizing.
+    pub fn begin_ui_pass(&mut self) {
+        let mut input = self.egui_state.take_egui_input(&self.window);
+        let ppp = self.egui_state.egui_ctx().pixels_per_point();
+        input.screen_rect = Some(egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(self.extent.width as f32, self.extent.height as f32) / ppp,
+        ));
+        self.egui_state.egui_ctx().begin_pass(input);
+    }
+
+    /// The device must be idle.
+    pub unsafe fn destroy(&mut self, shared: &Shared) {
+        let device = &shared.device;
+        self.clean_swapchain(shared);
+        shared.swap_device.destroy_swapchain(self.swapchain, None);
+        device.destroy_render_pass(self.output_pass, None);
+        self.tonemapper.destroy(device);
+        self.egui_renderer.destroy(device);
+        for frame in &mut self.frames {
+            // the pool outlives windows that are closed early
+            device.free_command_buffers(shared.command_pool, &[frame.cmd]);
+            frame.destroy(device);
+        }
+        shared.surface_loader.destroy_surface(self.surface, None);
+    }
+}
@@ -1,23 +1,215 @@
 #![cfg_attr(target_arch = "spirv", no_std)]
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
 use spirv_std::{
-    glam::{vec2, vec3, Vec2, Vec3, Vec4},
-    spirv,
+    arch,
+    glam::{vec2, vec3, Mat3, Mat4, Vec2, Vec3, Vec4},
+    image::Image2d,
+    spirv, Sampler,
 };
 
 #[allow(dead_code)]
 #[spirv(vertex)]
-pub fn vert_main(
+pub fn sprite_vert(
     in_pos: Vec2,
-    in_col: Vec3,
+    in_uv: Vec2,
+    in_col: Vec4,
+    #[spirv(push_constant)] proj: &Mat4,
     #[spirv(position)] position: &mut Vec4,
-    out: &mut Vec3,
+    out_uv: &mut Vec2,
+    out_col: &mut Vec4,
 ) {
-    *position = in_pos.extend(0.0).extend(1.0);
+    *position = *proj * in_pos.extend(0.0).extend(1.0);
+    *out_uv = in_uv;
+    *out_col = in_col;
+}
+
+#[allow(dead_code)]
+#[spirv(fragment)]
+pub fn sprite_frag(
+    in_uv: Vec2,
+    in_col: Vec4,
+    #[spirv(descriptor_set = 0, binding = 0)] tex: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    out: &mut Vec4,
+) {
+    let texel: Vec4 = tex.sample(*sampler, in_uv);
+    *out = texel * in_col;
+}
+
+#[allow(dead_code)]
+#[spirv(vertex)]
+pub fn debug_vert(
+    in_pos: Vec3,
+    in_col: Vec4,
+    #[spirv(push_constant)] view_proj: &Mat4,
+    #[spirv(position)] position: &mut Vec4,
+    out_col: &mut Vec4,
+) {
+    *position = *view_proj * in_pos.extend(1.0);
+    *out_col = in_col;
+}
+
+#[allow(dead_code)]
+#[spirv(fragment)]
+pub fn debug_frag(in_col: Vec4, out: &mut Vec4) {
     *out = in_col;
 }
 
 #[allow(dead_code)]
 #[spirv(fragment)]
-pub fn frag_main(frag_color: Vec3, out: &mut Vec4) {
-    *out = frag_color.extend(1.0);
+pub fn text_frag(
+    in_uv: Vec2,
+    in_col: Vec4,
+    #[spirv(descriptor_set = 0, binding = 0)] atlas: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    out: &mut Vec4,
+) {
+    let coverage: Vec4 = atlas.sample(*sampler, in_uv);
+    *out = in_col.truncate().extend(in_col.w * coverage.x);
+}
+
+#[allow(dead_code)]
+#[spirv(fragment)]
+pub fn text_sdf_frag(
+    in_uv: Vec2,
+    in_col: Vec4,
+    #[spirv(descriptor_set = 0, binding = 0)] atlas: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    out: &mut Vec4,
+) {
+    let dist: Vec4 = atlas.sample(*sampler, in_uv);
+    // antialias over roughly one screen pixel, whatever the glyph's scale
+    let width = arch::fwidth(dist.x).max(1.0e-4);
+    let t = ((dist.x - 0.5 + width) / (2.0 * width)).clamp(0.0, 1.0);
+    let alpha = t * t * (3.0 - 2.0 * t);
+    *out = in_col.truncate().extend(in_col.w * alpha);
+}
+
+fn linear_from_srgb(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn srgb_from_linear(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// SMPTE ST 2084 (PQ), for light normalized so that 1.0 is 10000 nits.
+fn pq_from_linear(c: f32) -> f32 {
+    let (m1, m2) = (0.1593017578125, 78.84375);
+    let (c1, c2, c3) = (0.8359375, 18.8515625, 18.6875);
+    let p = c.max(0.0).powf(m1);
+    ((c1 + c2 * p) / (1.0 + c3 * p)).powf(m2)
+}
+
+/// Encodes linear Rec.709 color for the swapchain. `output.x` selects the encoding: 0 leaves it
+/// to an sRGB format, 1 is the sRGB curve, 2 extended sRGB linear, 3 HDR10. `output.y` is the
+/// brightness of 1.0 in nits.
+fn encode_output(c: Vec3, output: Vec2) -> Vec3 {
+    match output.x as u32 {
+        1 => vec3(
+            srgb_from_linear(c.x),
+            srgb_from_linear(c.y),
+            srgb_from_linear(c.z),
+        ),
+        // 1.0 is 80 nits in extended sRGB
+        2 => c * (output.y / 80.0),
+        3 => {
+            let rec2020 = Mat3::from_cols(
+                vec3(0.6274, 0.0691, 0.0164),
+                vec3(0.3293, 0.9195, 0.0880),
+                vec3(0.0433, 0.0114, 0.8956),
+            ) * c
+                * (output.y / 10000.0);
+            vec3(
+                pq_from_linear(rec2020.x),
+                pq_from_linear(rec2020.y),
+                pq_from_linear(rec2020.z),
+            )
+        }
+        _ => c,
+    }
+}
+
+#[allow(dead_code)]
+#[spirv(vertex)]
+pub fn egui_vert(
+    in_pos: Vec2,
+    in_uv: Vec2,
+    in_col: Vec4,
+    #[spirv(push_constant)] params: &Vec4,
+    #[spirv(position)] position: &mut Vec4,
+    out_uv: &mut Vec2,
+    out_col: &mut Vec4,
+) {
+    // egui positions are in points with the origin at the top left
+    let ndc = 2.0 * in_pos / params.truncate().truncate() - Vec2::ONE;
+    *position = ndc.extend(0.0).extend(1.0);
+    *out_uv = in_uv;
+    // vertex colors are sRGB, but blending happens in linear space
+    *out_col = vec3(
+        linear_from_srgb(in_col.x),
+        linear_from_srgb(in_col.y),
+        linear_from_srgb(in_col.z),
+    )
+    .extend(in_col.w);
+}
+
+#[allow(dead_code)]
+#[spirv(fragment)]
+pub fn egui_frag(
+    in_uv: Vec2,
+    in_col: Vec4,
+    #[spirv(push_constant)] params: &Vec4,
+    #[spirv(descriptor_set = 0, binding = 0)] tex: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    out: &mut Vec4,
+) {
+    let texel: Vec4 = tex.sample(*sampler, in_uv);
+    let col = texel * in_col;
+    *out = encode_output(col.truncate(), vec2(params.z, params.w)).extend(col.w);
+}
+
+/// A triangle covering the screen, drawn without vertex buffers.
+#[allow(dead_code)]
+#[spirv(vertex)]
+pub fn fullscreen_vert(
+    #[spirv(vertex_index)] idx: i32,
+    #[spirv(position)] position: &mut Vec4,
+    out_uv: &mut Vec2,
+) {
+    let uv = vec2(((idx << 1) & 2) as f32, (idx & 2) as f32);
+    *position = (uv * 2.0 - Vec2::ONE).extend(0.0).extend(1.0);
+    *out_uv = uv;
+}
+
+/// Tone maps the HDR scene for the output. `params` holds the encoding and paper white as in
+/// `encode_output`, then the exposure and the display's peak brightness in nits.
+#[allow(dead_code)]
+#[spirv(fragment)]
+pub fn tonemap_frag(
+    in_uv: Vec2,
+    #[spirv(push_constant)] params: &Vec4,
+    #[spirv(descriptor_set = 0, binding = 0)] scene: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    out: &mut Vec4,
+) {
+    let hdr: Vec4 = scene.sample(*sampler, in_uv);
+    let c = hdr.truncate() * params.z;
+    let mapped = if params.x < 1.5 {
+        // ACES filmic fit (Narkowicz) for SDR
+        ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(Vec3::ZERO, Vec3::ONE)
+    } else {
+        // HDR keeps the range up to the display's peak
+        c.clamp(Vec3::ZERO, Vec3::splat(params.w / params.y))
+    };
+    *out = encode_output(mapped, vec2(params.x, params.y)).extend(1.0);
 }